@@ -21,7 +21,8 @@ pub struct Args {
     #[arg(long, default_value_t = false)]
     pub dry_run: bool,
 
-    /// No kill
+    /// Do not close the windows already open. On niri, the ones matching the tree are kept in
+    /// place of spawning them, unless `existing_windows: duplicate` is set in the config
     #[arg(long, default_value_t = false)]
     pub no_kill: bool,
 }
@@ -36,5 +37,13 @@ pub enum Mode {
         /// Other workspaces app will not be killed, and only this workspace apps will be loaded from config file.
         #[arg(long)]
         workspace: Option<String>,
+
+        /// Keep the windows already in place, close only the extra ones and spawn only the missing ones.
+        #[arg(long, default_value_t = false)]
+        reconcile: bool,
+
+        /// Spawn all the apps at once, then put their windows in place.
+        #[arg(long, default_value_t = false)]
+        parallel: bool,
     },
 }
@@ -0,0 +1,1034 @@
+use std::{path::Path, time::Instant};
+
+use anyhow::{Context, Result, bail};
+use tracing::{info, warn};
+
+use crate::{
+    config::{Config, ExistingWindows, OnFailure},
+    consts::MAX_WAIT_DURATION,
+    models::{Compositor, Exec, Node, NodeType, load_tree, save_tree},
+    reconcile, util,
+};
+
+/// Starts a command, in a working directory when given, and returns its pid: `util::launch`,
+/// but the fakes stand in for it in tests.
+pub type Launch = Box<dyn FnMut(&Exec, Option<&Path>) -> Result<i32>>;
+
+/// The launcher starting the apps for real.
+pub fn launcher() -> Launch {
+    Box::new(|exec, cwd| util::launch(exec, cwd).context(format!("on launch(\"{exec}\")")))
+}
+
+/// A compositor we know how to save a tree from, and load a tree into.
+///
+/// `save` and `load` drive the whole pipeline, a backend only has to
+/// provide the compositor specific steps.
+pub trait Backend {
+    /// Returns the current tree, one node per workspace.
+    fn get_tree(&mut self) -> Result<Vec<Node>>;
+
+    /// Closes the current windows, only the ones of `workspace` if given.
+    fn clear(&mut self, workspace: Option<&str>) -> Result<()>;
+
+    /// Closes the live window with the given id.
+    fn close_window(&mut self, id: i64) -> Result<()>;
+
+    /// Focuses the saved workspace `node`, found by its name,
+    /// so the next spawned windows land on it.
+    fn focus_workspace(&mut self, node: &Node) -> Result<()>;
+
+    /// Spawns the windows of a workspace node, rebuilding its layout around the ones kept
+    /// by a reconcile, which are not spawned again.
+    fn load_workspace(&mut self, node: &Node) -> Result<()>;
+
+    /// Runs once every workspace has been loaded.
+    fn post_load(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// The configuration the load follows.
+    fn config(&self) -> &Config;
+
+    /// The apps spawned so far.
+    fn spawner(&mut self) -> &mut Spawner;
+
+    /// Starts listening for the windows showing up, the ones already open are not given.
+    fn window_events(&mut self) -> Result<Box<dyn WindowEvents>>;
+
+    /// Moves the spawned window `id` to the workspace being loaded, wherever it showed up.
+    fn place(&mut self, id: i64) -> Result<()>;
+
+    /// Focuses a workspace out of the way of the layouts being rebuilt, for `spawn_all`.
+    fn focus_spawn_workspace(&mut self) -> Result<()>;
+
+    /// Spawns the application of a window node and waits for its window to show up, then
+    /// places it. Returns the id of the new window, when known.
+    ///
+    /// A window opened by `spawn_all` is taken rather than spawned again. The app is launched
+    /// up to `retry` times, then `on_failure` tells whether to go on without it.
+    fn spawn_and_wait(&mut self, node: &Node) -> Result<Option<i64>> {
+        let Some(exec) = spawn_command(self.config(), node) else {
+            return Ok(None);
+        };
+        let cmd = exec.to_string();
+
+        // a window opened by `spawn_all` for the same command and directory, if it is this one
+        let spawner = self.spawner();
+        if let Some(idx) = spawner.spawned.iter().position(|(spawned, window)| {
+            *spawned == cmd && window.cwd == node.cwd && node.matches(window)
+        }) {
+            let (_, window) = spawner.spawned.remove(idx);
+            let id = window.id.context("spawned window without id")?;
+            self.place(id)?;
+            return Ok(Some(id));
+        }
+
+        info!("exec {cmd:?}");
+        if spawner.dry_run {
+            return Ok(None);
+        }
+        // launched by `spawn_all` but late, waiting for it is the first try
+        let mut late = spawner.late.as_ref().and_then(|late| {
+            late.apps
+                .iter()
+                .find(|(spawned, app, _)| *spawned == cmd && app.cwd == node.cwd)
+                .map(|(_, _, pid)| *pid)
+        });
+        let on_failure = self.config().on_failure;
+        let retry = node.retry.unwrap_or(1);
+        for i in 0..retry {
+            if i > 0 {
+                info!("retrying {cmd:?}");
+            }
+            // nothing tells its window apart, it is not waited for
+            if !node.is_identifiable() {
+                match (self.spawner().launch)(&exec, node.cwd.as_deref()) {
+                    Ok(_) => return Ok(None),
+                    Err(e) => {
+                        warn!("{e:#}");
+                        continue;
+                    }
+                }
+            }
+            // `wait` only lifts the timeout of the last try
+            let timeout = match on_failure {
+                OnFailure::Wait if i + 1 == retry => None,
+                _ => Some(node.timeout.unwrap_or(MAX_WAIT_DURATION)),
+            };
+            let deadline = timeout.map(|timeout| Instant::now() + timeout);
+            let result = match late.take() {
+                Some(pid) => self.spawner().wait_late(pid, deadline),
+                None => launch_and_wait(self, &exec, node, deadline),
+            };
+            match result {
+                Ok(id) => {
+                    if let Some(late) = &mut self.spawner().late {
+                        late.placed.push(id);
+                    }
+                    self.place(id)?;
+                    return Ok(Some(id));
+                }
+                Err(e) => warn!("{e:#}"),
+            }
+        }
+
+        match on_failure {
+            OnFailure::Abort => bail!("{cmd:?} did not spawn after {retry} tries"),
+            _ => {
+                warn!("skipping {cmd:?}, it did not spawn");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Spawns the window nodes all at once and waits for their windows, without placing them:
+    /// `spawn_and_wait` then takes these windows instead of spawning their apps again, or
+    /// waits for the ones that did not show up in time.
+    fn spawn_all(&mut self, nodes: &[&Node]) -> Result<()> {
+        if self.spawner().dry_run {
+            info!("dry run, the apps are spawned one at a time");
+            return Ok(());
+        }
+        self.focus_spawn_workspace()
+            .context("on focus_spawn_workspace()")?;
+        let mut events = self.window_events().context("on window_events()")?;
+
+        // a window that can not be told apart is spawned when placed
+        let mut launched = vec![];
+        let mut cmds = vec![];
+        let mut deadline = Instant::now();
+        for &node in nodes.iter().filter(|node| node.is_identifiable()) {
+            let Some(exec) = spawn_command(self.config(), node) else {
+                continue;
+            };
+            let cmd = exec.to_string();
+            info!("exec {cmd:?}");
+            match (self.spawner().launch)(&exec, node.cwd.as_deref()) {
+                Ok(pid) => launched.push((node, pid)),
+                Err(e) => {
+                    warn!("{e:#}, it is launched again when placed");
+                    continue;
+                }
+            }
+            cmds.push(cmd);
+            deadline = deadline.max(Instant::now() + node.timeout.unwrap_or(MAX_WAIT_DURATION));
+        }
+
+        let spawner = self.spawner();
+        while !launched.is_empty() {
+            let Some(window) = events
+                .next_window(Some(deadline))
+                .context("on next_window()")?
+            else {
+                break;
+            };
+            if spawner.is_taken(&window) {
+                continue;
+            }
+            if let Some(idx) = owner(&launched, &window) {
+                let (node, _) = launched.remove(idx);
+                let cwd = node.cwd.clone();
+                spawner
+                    .spawned
+                    .push((cmds.remove(idx), Node { cwd, ..window }));
+            }
+        }
+        for cmd in &cmds {
+            warn!("{cmd:?} did not show up in time, it is waited for when placed");
+        }
+        if !launched.is_empty() {
+            let apps = cmds
+                .into_iter()
+                .zip(launched)
+                .map(|(cmd, (node, pid))| (cmd, node.clone(), pid))
+                .collect();
+            spawner.late = Some(Late {
+                events,
+                apps,
+                placed: vec![],
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The windows showing up, as `window_events` gives them.
+pub trait WindowEvents {
+    /// The next window to show up, or to change, with what tells it apart, see
+    /// `Node::matches`. `None` once `deadline` is passed, without it, it waits until one does.
+    fn next_window(&mut self, deadline: Option<Instant>) -> Result<Option<Node>>;
+}
+
+/// The apps spawned by a load: the windows `spawn_all` opened and the ones it still waits for.
+pub struct Spawner {
+    /// Starts the apps, giving their pid.
+    pub launch: Launch,
+    dry_run: bool,
+    /// Windows opened by `spawn_all` and not placed yet, with the command that spawned them,
+    /// in spawn order. Their `cwd` is the directory they were spawned in.
+    spawned: Vec<(String, Node)>,
+    /// The apps launched by `spawn_all` whose window did not show up in time.
+    late: Option<Late>,
+}
+
+/// The apps launched by `spawn_all` whose window did not show up in time, awaited when placed.
+struct Late {
+    events: Box<dyn WindowEvents>,
+    /// The command, node and pid of each app.
+    apps: Vec<(String, Node, i32)>,
+    /// The windows placed meanwhile, not to be taken for theirs.
+    placed: Vec<i64>,
+}
+
+impl Spawner {
+    pub fn new(dry_run: bool) -> Self {
+        Self {
+            launch: launcher(),
+            dry_run,
+            spawned: vec![],
+            late: None,
+        }
+    }
+
+    /// Whether `window` already went to a node, as given again on a change.
+    fn is_taken(&self, window: &Node) -> bool {
+        let placed = self.late.as_ref().map_or(&[][..], |late| &late.placed);
+        window.id.is_some_and(|id| placed.contains(&id))
+            || self
+                .spawned
+                .iter()
+                .any(|(_, spawned)| spawned.id == window.id)
+    }
+
+    /// Waits for the window of the app `pid` launched by `spawn_all`, keeping the windows of the
+    /// other late apps showing up meanwhile.
+    fn wait_late(&mut self, pid: i32, deadline: Option<Instant>) -> Result<i64> {
+        loop {
+            let late = self.late.as_mut().context("no app launched by spawn_all")?;
+            let Some(window) = late
+                .events
+                .next_window(deadline)
+                .context("on next_window()")?
+            else {
+                break;
+            };
+            if self.is_taken(&window) {
+                continue;
+            }
+            let late = self.late.as_mut().context("no app launched by spawn_all")?;
+            let apps: Vec<_> = late
+                .apps
+                .iter()
+                .map(|(_, node, pid)| (node, *pid))
+                .collect();
+            let Some(idx) = owner(&apps, &window) else {
+                continue;
+            };
+            let (cmd, node, owner) = late.apps.remove(idx);
+            if owner == pid {
+                return window.id.context("spawned window without id");
+            }
+            let cwd = node.cwd;
+            self.spawned.push((cmd, Node { cwd, ..window }));
+        }
+
+        // given up on, a retry launches it again
+        let late = self.late.as_mut().context("no app launched by spawn_all")?;
+        let idx = late.apps.iter().position(|(_, _, app)| *app == pid);
+        let (cmd, _, _) = late.apps.remove(idx.context("unknown late app")?);
+        bail!("Timed out waiting for {cmd:?} to spawn");
+    }
+}
+
+/// Launches `exec` and waits for its window, matching `node`, to show up, returning its id.
+fn launch_and_wait<B: Backend + ?Sized>(
+    backend: &mut B,
+    exec: &Exec,
+    node: &Node,
+    deadline: Option<Instant>,
+) -> Result<i64> {
+    // listening before launching, so the window can not be missed
+    let mut events = backend.window_events().context("on window_events()")?;
+    let pid = (backend.spawner().launch)(exec, node.cwd.as_deref())?;
+
+    while let Some(window) = events.next_window(deadline).context("on next_window()")? {
+        if owner(&[(node, pid)], &window).is_some() {
+            return window.id.context("spawned window without id");
+        }
+    }
+    bail!("Timed out waiting for {:?} to spawn", exec.to_string());
+}
+
+pub fn save(backend: &mut dyn Backend, tree_path: &Path, dry_run: bool) -> Result<()> {
+    let tree = backend.get_tree().context("on get_tree()")?;
+
+    if dry_run {
+        println!("tree[{tree_path:?}]:\n{tree:?}");
+        return Ok(());
+    }
+
+    save_tree(tree_path, &tree).context("on save_tree()")?;
+    info!("tree saved into: {tree_path:?}");
+
+    Ok(())
+}
+
+/// Loads the tree at `tree_path`, only its `workspace` if given.
+///
+/// With `reconcile`, windows already matching the saved tree are kept, rather than closing
+/// everything and spawning it back. With `parallel`, all the apps are spawned at once before
+/// the layout is rebuilt, so loading takes about as long as the slowest app.
+pub fn load(
+    backend: &mut dyn Backend,
+    tree_path: &Path,
+    workspace: Option<&str>,
+    reconcile: bool,
+    parallel: bool,
+) -> Result<()> {
+    info!("loading tree from {tree_path:?}");
+    let mut tree = load_tree(tree_path).context("on load_tree()")?;
+
+    if reconcile {
+        let live = backend.get_tree().context("on get_tree()")?;
+        for id in reconcile::reconcile(&mut tree, &live, workspace) {
+            backend
+                .close_window(id)
+                .context(format!("on close_window({id})"))?;
+        }
+    } else {
+        backend.clear(workspace).context("on clear()")?;
+    }
+
+    let mut workspaces = vec![];
+    for (idx, node) in tree.iter().enumerate() {
+        if !matches!(node.node_type, NodeType::Root | NodeType::Workspace) {
+            warn!(
+                "skipping: uncompatible node at idx={idx}, looking for Workspace: {}",
+                node.node_type
+            );
+            continue;
+        }
+        if workspace.is_some() && node.name.as_deref() != workspace {
+            continue;
+        }
+        workspaces.push(node);
+    }
+
+    if parallel {
+        let windows: Vec<_> = workspaces
+            .iter()
+            .flat_map(|node| reconcile::windows(node))
+            .filter(|window| window.id.is_none())
+            .collect();
+        backend.spawn_all(&windows).context("on spawn_all()")?;
+    }
+
+    for node in workspaces {
+        backend
+            .focus_workspace(node)
+            .context(format!("on focus_workspace({:?})", node.name))?;
+        backend
+            .load_workspace(node)
+            .context(format!("on load_workspace({:?})", node.name))?;
+    }
+
+    backend.post_load().context("on post_load()")
+}
+
+/// Whether a load with `--no-kill` keeps the open windows matching the saved ones, as a
+/// reconcile does, rather than spawning every saved window next to them.
+///
+/// Only niri adopts them, unless `existing_windows` is `duplicate`: sway always spawns them all.
+pub fn adopts(compositor: Compositor, cfg: &Config, no_kill: bool) -> bool {
+    compositor == Compositor::Niri && no_kill && cfg.existing_windows == ExistingWindows::Adopt
+}
+
+/// Finds which of the `launched` nodes, started as the paired pids, `window` belongs to.
+///
+/// A window of a launched process, or of one of its descendants, belongs to its node as long as
+/// it matches it: two apps with the same app_id are not mixed up, whichever shows up first.
+/// A window of another process, as when an app hands over to its running instance, goes to
+/// the first node it matches.
+pub fn owner(launched: &[(&Node, i32)], window: &Node) -> Option<usize> {
+    let own = window.pid.and_then(|pid| {
+        launched
+            .iter()
+            .position(|&(_, launched)| util::descends_from(pid, launched))
+    });
+    match own {
+        Some(idx) => {
+            let (node, _) = launched[idx];
+            (!node.is_identifiable() || node.matches(window)).then_some(idx)
+        }
+        None => launched.iter().position(|(node, _)| node.matches(window)),
+    }
+}
+
+/// Saves what the terminals of `cfg.terminals` run: the tmux or zellij session they show,
+/// or else as their exec, the program in the foreground of their shell, through the command
+/// of the terminal. A terminal waiting for a command keeps its own command line.
+pub fn capture_terminals(cfg: &Config, node: &mut Node) {
+    if let (Some(pid), Some(app_id)) = (node.pid, &node.app_id)
+        && let Some(template) = cfg.terminals.get(app_id)
+    {
+        if let Some(session) = util::extract_session(&pid) {
+            node.session = Some(session);
+        } else if let Some(program) = util::extract_program(&pid) {
+            node.exec = Some(Exec::Argv(
+                template.iter().cloned().chain(program).collect(),
+            ));
+        }
+    }
+    for child in node.nodes.iter_mut() {
+        capture_terminals(cfg, child);
+    }
+}
+
+/// The command spawning the app of `node`: attached to its session for a terminal, then from
+/// its desktop entry, its exec, or else its app_id.
+pub fn spawn_command(cfg: &Config, node: &Node) -> Option<Exec> {
+    if let Some(attach) = attach_command(cfg, node) {
+        Some(attach)
+    } else if let Some(desktop_file) = &node.desktop_entry {
+        Some(Exec::Shell(format!(
+            "{} \"{}\"",
+            cfg.desktop_exec,
+            desktop_file.replace("\"", "\\\"")
+        )))
+    } else if let Some(exec) = &node.exec {
+        Some(exec.clone())
+    } else {
+        node.app_id.clone().map(Exec::Shell)
+    }
+}
+
+/// The command starting the terminal of `node` attached to its session, when it has one
+/// and the terminal is in `cfg.terminals`.
+pub fn attach_command(cfg: &Config, node: &Node) -> Option<Exec> {
+    let session = node.session.as_ref()?;
+    let template = cfg.terminals.get(node.app_id.as_ref()?)?;
+    let args = template.iter().cloned().chain(session.attach_command());
+    Some(Exec::Argv(args.collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, path::PathBuf, rc::Rc, thread, time::Duration};
+
+    use super::*;
+    use crate::models::Match;
+
+    /// The pid of the first app the stub launches, above the pids of real processes.
+    const FIRST_PID: i32 = 1 << 23;
+
+    /// The window an app opens once launched.
+    #[derive(Clone, Default)]
+    struct App {
+        command: String,
+        /// Only launched in this directory, when given.
+        cwd: Option<PathBuf>,
+        window: Node,
+        /// How long after its launch the window shows up.
+        delay: Duration,
+        /// The number of launches lost before one opens the window.
+        skip: usize,
+    }
+
+    fn app(app_id: &str) -> App {
+        App {
+            command: app_id.to_string(),
+            window: Node {
+                app_id: Some(app_id.to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// What the stub went through, shared with its launcher and its window events.
+    #[derive(Default)]
+    struct State {
+        /// The apps it knows how to start, any other command fails to.
+        apps: Vec<App>,
+        /// The windows opened so far, with when they show up.
+        windows: Vec<(Instant, Node)>,
+        /// The load as it went: `launch <command>`, `place <title or app_id>` and
+        /// `spawn workspace` when it was focused.
+        steps: Vec<String>,
+    }
+
+    /// A compositor whose apps open the window of their `App`, with the pid of their launch.
+    struct Stub {
+        cfg: Config,
+        spawner: Spawner,
+        state: Rc<RefCell<State>>,
+    }
+
+    impl Stub {
+        fn new(cfg: Config, apps: Vec<App>) -> Self {
+            let state = Rc::new(RefCell::new(State {
+                apps,
+                ..Default::default()
+            }));
+            let mut spawner = Spawner::new(false);
+            let launched = Rc::clone(&state);
+            spawner.launch = Box::new(move |exec, cwd| {
+                let cmd = exec.to_string();
+                let mut state = launched.borrow_mut();
+                let Some(app) = state.apps.iter_mut().find(|app| {
+                    app.command == cmd && (app.cwd.is_none() || app.cwd.as_deref() == cwd)
+                }) else {
+                    bail!("on spawn({cmd}): No such file or directory (os error 2)")
+                };
+                let lost = app.skip > 0;
+                app.skip = app.skip.saturating_sub(1);
+                let app = app.clone();
+                state.steps.push(format!("launch {cmd}"));
+                let pid = FIRST_PID + state.steps.len() as i32;
+                if !lost {
+                    let id = state.windows.len() as i64 + 1;
+                    let window = Node {
+                        id: Some(id),
+                        pid: Some(pid),
+                        ..app.window
+                    };
+                    state.windows.push((Instant::now() + app.delay, window));
+                }
+                Ok(pid)
+            });
+            Self {
+                cfg,
+                spawner,
+                state,
+            }
+        }
+
+        fn steps(&self) -> Vec<String> {
+            self.state.borrow().steps.clone()
+        }
+    }
+
+    impl Backend for Stub {
+        fn get_tree(&mut self) -> Result<Vec<Node>> {
+            Ok(vec![])
+        }
+
+        fn clear(&mut self, _workspace: Option<&str>) -> Result<()> {
+            Ok(())
+        }
+
+        fn close_window(&mut self, _id: i64) -> Result<()> {
+            Ok(())
+        }
+
+        fn focus_workspace(&mut self, _node: &Node) -> Result<()> {
+            Ok(())
+        }
+
+        fn load_workspace(&mut self, _node: &Node) -> Result<()> {
+            Ok(())
+        }
+
+        fn config(&self) -> &Config {
+            &self.cfg
+        }
+
+        fn spawner(&mut self) -> &mut Spawner {
+            &mut self.spawner
+        }
+
+        fn window_events(&mut self) -> Result<Box<dyn WindowEvents>> {
+            Ok(Box::new(Events {
+                state: Rc::clone(&self.state),
+                since: Instant::now(),
+                given: vec![],
+            }))
+        }
+
+        fn place(&mut self, id: i64) -> Result<()> {
+            let mut state = self.state.borrow_mut();
+            let (_, window) = state
+                .windows
+                .iter()
+                .find(|(_, window)| window.id == Some(id))
+                .context("unknown window")?;
+            let name = window.title.clone().or(window.app_id.clone());
+            state
+                .steps
+                .push(format!("place {}", name.unwrap_or_default()));
+            Ok(())
+        }
+
+        fn focus_spawn_workspace(&mut self) -> Result<()> {
+            self.state
+                .borrow_mut()
+                .steps
+                .push("spawn workspace".to_string());
+            Ok(())
+        }
+    }
+
+    /// The windows of the stub showing up after `since`, in the order they do.
+    struct Events {
+        state: Rc<RefCell<State>>,
+        since: Instant,
+        given: Vec<i64>,
+    }
+
+    impl WindowEvents for Events {
+        fn next_window(&mut self, deadline: Option<Instant>) -> Result<Option<Node>> {
+            let next = self
+                .state
+                .borrow()
+                .windows
+                .iter()
+                .filter(|(at, window)| {
+                    *at >= self.since && !self.given.contains(&window.id.unwrap())
+                })
+                .min_by_key(|(at, _)| *at)
+                .cloned();
+            match next {
+                Some((at, window)) if deadline.is_none_or(|deadline| at <= deadline) => {
+                    thread::sleep(at.saturating_duration_since(Instant::now()));
+                    self.given.extend(window.id);
+                    Ok(Some(window))
+                }
+                _ => {
+                    let deadline = deadline.context("no window left to show up")?;
+                    thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    /// Spawns the apps of `windows` in order, all at once beforehand with `parallel`,
+    /// as `load` does.
+    fn load(stub: &mut Stub, windows: &[Node], parallel: bool) -> Result<()> {
+        if parallel {
+            stub.spawn_all(&windows.iter().collect::<Vec<_>>())?;
+        }
+        for window in windows {
+            stub.spawn_and_wait(window)?;
+        }
+        Ok(())
+    }
+
+    fn window(app_id: &str, retry: u8, timeout_ms: u64) -> Node {
+        Node {
+            app_id: Some(app_id.to_string()),
+            retry: Some(retry),
+            timeout: Some(Duration::from_millis(timeout_ms)),
+            ..Default::default()
+        }
+    }
+
+    fn on_failure(on_failure: OnFailure) -> Config {
+        Config {
+            on_failure,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn retries_apps_that_do_not_show_up() {
+        let flaky = App {
+            skip: 1,
+            ..app("flaky")
+        };
+        let mut stub = Stub::new(Config::default(), vec![flaky]);
+
+        load(&mut stub, &[window("flaky", 3, 100)], false).unwrap();
+
+        // the first launch is lost, the second one opens the window, no third try
+        assert_eq!(
+            stub.steps(),
+            ["launch flaky", "launch flaky", "place flaky"]
+        );
+    }
+
+    #[test]
+    fn goes_on_without_apps_that_never_show_up() {
+        let never = App {
+            skip: usize::MAX,
+            ..app("never")
+        };
+        let mut stub = Stub::new(Config::default(), vec![never, app("foot")]);
+
+        load(
+            &mut stub,
+            &[window("never", 2, 100), window("foot", 1, 100)],
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            stub.steps(),
+            ["launch never", "launch never", "launch foot", "place foot"]
+        );
+    }
+
+    #[test]
+    fn aborts_on_apps_that_never_show_up_when_configured() {
+        let never = App {
+            skip: usize::MAX,
+            ..app("never")
+        };
+        let windows = [window("never", 2, 100), window("foot", 1, 100)];
+
+        let mut stub = Stub::new(
+            on_failure(OnFailure::Abort),
+            vec![never.clone(), app("foot")],
+        );
+        assert!(load(&mut stub, &windows, false).is_err());
+        assert_eq!(stub.steps(), ["launch never", "launch never"]);
+
+        // foot showed up meanwhile, but is never placed
+        let mut stub = Stub::new(on_failure(OnFailure::Abort), vec![never, app("foot")]);
+        assert!(load(&mut stub, &windows, true).is_err());
+        assert_eq!(
+            stub.steps(),
+            [
+                "spawn workspace",
+                "launch never",
+                "launch foot",
+                "launch never"
+            ]
+        );
+    }
+
+    #[test]
+    fn waits_past_the_timeout_for_slow_apps_when_configured() {
+        let slow = App {
+            delay: Duration::from_millis(300),
+            ..app("slow")
+        };
+        let mut stub = Stub::new(on_failure(OnFailure::Wait), vec![slow]);
+
+        load(&mut stub, &[window("slow", 1, 100)], false).unwrap();
+
+        assert_eq!(stub.steps(), ["launch slow", "place slow"]);
+    }
+
+    #[test]
+    fn skips_apps_that_fail_to_launch() {
+        let missing = Node {
+            exec: Some(Exec::Argv(vec!["missing".to_string()])),
+            ..window("missing", 2, 100)
+        };
+        let windows = [missing, window("foot", 1, 100)];
+
+        let mut stub = Stub::new(Config::default(), vec![app("foot")]);
+        load(&mut stub, &windows, false).unwrap();
+        assert_eq!(stub.steps(), ["launch foot", "place foot"]);
+
+        let mut stub = Stub::new(Config::default(), vec![app("foot")]);
+        load(&mut stub, &windows, true).unwrap();
+        assert_eq!(
+            stub.steps(),
+            ["spawn workspace", "launch foot", "place foot"]
+        );
+    }
+
+    #[test]
+    fn launches_apps_that_can_not_be_told_apart_without_waiting_for_them() {
+        let notify = App {
+            command: "notify-send hi".to_string(),
+            skip: usize::MAX,
+            ..Default::default()
+        };
+        let windows = [
+            Node {
+                exec: Some(Exec::Shell("notify-send hi".to_string())),
+                ..Default::default()
+            },
+            window("foot", 1, 100),
+        ];
+
+        let mut stub = Stub::new(Config::default(), vec![notify.clone(), app("foot")]);
+        load(&mut stub, &windows, false).unwrap();
+        assert_eq!(
+            stub.steps(),
+            ["launch notify-send hi", "launch foot", "place foot"]
+        );
+
+        // not spawned with the others, but when its turn comes
+        let mut stub = Stub::new(Config::default(), vec![notify, app("foot")]);
+        load(&mut stub, &windows, true).unwrap();
+        assert_eq!(
+            stub.steps(),
+            [
+                "spawn workspace",
+                "launch foot",
+                "launch notify-send hi",
+                "place foot"
+            ]
+        );
+    }
+
+    #[test]
+    fn spawns_all_the_apps_before_placing_their_windows_in_order() {
+        // the last app shows up first
+        let apps = [("foot", 200), ("htop", 100), ("firefox", 0)]
+            .into_iter()
+            .map(|(app_id, delay)| App {
+                delay: Duration::from_millis(delay),
+                ..app(app_id)
+            })
+            .collect();
+        let mut stub = Stub::new(Config::default(), apps);
+        let windows = [
+            window("foot", 1, 1000),
+            window("htop", 1, 1000),
+            window("firefox", 1, 1000),
+        ];
+
+        load(&mut stub, &windows, true).unwrap();
+
+        assert_eq!(
+            stub.steps(),
+            [
+                "spawn workspace",
+                "launch foot",
+                "launch htop",
+                "launch firefox",
+                "place foot",
+                "place htop",
+                "place firefox",
+            ]
+        );
+    }
+
+    #[test]
+    fn waits_for_late_apps_instead_of_launching_them_again() {
+        let slow = App {
+            delay: Duration::from_millis(300),
+            ..app("slow")
+        };
+        let mut stub = Stub::new(Config::default(), vec![slow, app("foot")]);
+
+        // given up on by `spawn_all`, but within its timeout once its turn comes
+        load(
+            &mut stub,
+            &[window("slow", 1, 200), window("foot", 1, 200)],
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(
+            stub.steps(),
+            [
+                "spawn workspace",
+                "launch slow",
+                "launch foot",
+                "place slow",
+                "place foot",
+            ]
+        );
+    }
+
+    #[test]
+    fn does_not_spawn_in_dry_run() {
+        let mut stub = Stub::new(Config::default(), vec![app("foot")]);
+        stub.spawner = Spawner::new(true);
+
+        for parallel in [false, true] {
+            load(&mut stub, &[window("foot", 1, 100)], parallel).unwrap();
+        }
+
+        assert!(stub.steps().is_empty());
+    }
+
+    #[test]
+    fn tells_apart_windows_of_the_same_app_id_by_title() {
+        // the second app shows up first
+        let apps = [("chat", 300), ("mail", 100)]
+            .into_iter()
+            .map(|(name, delay)| App {
+                command: format!("firefox --app={name}"),
+                window: Node {
+                    title: Some(format!("{name} - Firefox")),
+                    ..app("firefox").window
+                },
+                delay: Duration::from_millis(delay),
+                ..Default::default()
+            })
+            .collect();
+        let pwa = |name: &str| Node {
+            exec: Some(Exec::Shell(format!("firefox --app={name}"))),
+            matcher: Some(Match {
+                title: Some(format!("^{name} ")),
+                ..Default::default()
+            }),
+            ..window("firefox", 1, 1000)
+        };
+
+        let mut stub = Stub::new(Config::default(), apps);
+        load(&mut stub, &[pwa("chat"), pwa("mail")], true).unwrap();
+
+        assert_eq!(
+            stub.steps()[3..],
+            ["place chat - Firefox", "place mail - Firefox"]
+        );
+    }
+
+    #[test]
+    fn tells_apart_terminals_by_pid() {
+        // the second terminal shows up first
+        let apps = [("htop", 300), ("btop", 100)]
+            .into_iter()
+            .map(|(program, delay)| App {
+                command: format!("foot {program}"),
+                window: Node {
+                    title: Some(program.to_string()),
+                    ..app("foot").window
+                },
+                delay: Duration::from_millis(delay),
+                ..Default::default()
+            })
+            .collect();
+        let terminal = |program: &str| Node {
+            exec: Some(Exec::Argv(vec!["foot".to_string(), program.to_string()])),
+            ..window("foot", 1, 1000)
+        };
+
+        let mut stub = Stub::new(Config::default(), apps);
+        load(&mut stub, &[terminal("htop"), terminal("btop")], true).unwrap();
+
+        assert_eq!(stub.steps()[3..], ["place htop", "place btop"]);
+    }
+
+    #[test]
+    fn starts_terminals_in_their_directory() {
+        // the second terminal shows up first
+        let apps = [("api", 300), ("web", 100)]
+            .into_iter()
+            .map(|(project, delay)| App {
+                cwd: Some(PathBuf::from(format!("/src/{project}"))),
+                window: Node {
+                    title: Some(project.to_string()),
+                    ..app("foot").window
+                },
+                delay: Duration::from_millis(delay),
+                ..app("foot")
+            })
+            .collect();
+        let terminal = |project: &str| Node {
+            cwd: Some(PathBuf::from(format!("/src/{project}"))),
+            ..window("foot", 1, 1000)
+        };
+
+        let mut stub = Stub::new(Config::default(), apps);
+        load(&mut stub, &[terminal("api"), terminal("web")], true).unwrap();
+
+        assert_eq!(stub.steps()[3..], ["place api", "place web"]);
+    }
+
+    #[test]
+    fn finds_the_node_a_window_belongs_to() {
+        let foot = window("foot", 1, 100);
+        let htop = Node {
+            title: Some("htop".to_string()),
+            ..window("foot", 1, 100)
+        };
+        let launched = [(&foot, FIRST_PID), (&htop, FIRST_PID + 1)];
+        let shown = |pid: Option<i32>, app_id: &str| Node {
+            pid,
+            ..window(app_id, 1, 100)
+        };
+
+        // the window of a launched process goes to its node, whichever matches first
+        assert_eq!(
+            owner(&launched, &shown(Some(FIRST_PID + 1), "foot")),
+            Some(1)
+        );
+        // unless it does not match it
+        assert_eq!(
+            owner(&launched, &shown(Some(FIRST_PID + 1), "firefox")),
+            None
+        );
+        // the window of another process goes to the first node it matches
+        assert_eq!(owner(&launched, &shown(None, "foot")), Some(0));
+        assert_eq!(owner(&launched, &shown(Some(1), "foot")), Some(0));
+        assert_eq!(owner(&launched, &shown(None, "firefox")), None);
+
+        // nothing tells the window of a node without app_id apart but its pid
+        let unknown = Node::default();
+        let launched = [(&unknown, FIRST_PID)];
+        assert_eq!(owner(&launched, &shown(Some(FIRST_PID), "foot")), Some(0));
+        assert_eq!(owner(&launched, &shown(None, "foot")), None);
+    }
+
+    #[test]
+    fn adopts_existing_windows_with_no_kill_on_niri_only() {
+        let duplicate = Config {
+            existing_windows: ExistingWindows::Duplicate,
+            ..Default::default()
+        };
+
+        assert!(adopts(Compositor::Niri, &Config::default(), true));
+        assert!(!adopts(Compositor::Niri, &Config::default(), false));
+        assert!(!adopts(Compositor::Niri, &duplicate, true));
+        assert!(!adopts(Compositor::Sway, &Config::default(), true));
+    }
+}
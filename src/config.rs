@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     fs,
     path::{Path, PathBuf},
 };
@@ -15,6 +16,30 @@ fn default_desktop_exec() -> String {
     DEFAULT_DESKTOP_EXEC.to_string()
 }
 
+/// What loading with `--no-kill` on niri does with the windows already open.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExistingWindows {
+    /// Keep the ones matching saved windows in their place, spawn only the missing ones
+    #[default]
+    Adopt,
+    /// Spawn every saved window next to them
+    Duplicate,
+}
+
+/// What to do with an app whose window does not show up, once its retries are spent.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnFailure {
+    /// Go on loading without it
+    #[default]
+    Skip,
+    /// Stop the load with an error
+    Abort,
+    /// Keep waiting for it on the last try, past its timeout
+    Wait,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Config {
     /// The desktop launcher to use
@@ -23,12 +48,25 @@ pub struct Config {
         default = "default_desktop_exec"
     )]
     pub desktop_exec: String,
+    /// What to do with the windows already open when loading with `--no-kill` on niri
+    #[serde(default)]
+    pub existing_windows: ExistingWindows,
+    /// What to do with an app that does not show up
+    #[serde(default)]
+    pub on_failure: OnFailure,
+    /// Terminals, by app_id, whose running program is saved, with the command running a
+    /// program in them: the program and its arguments are appended, as in `[foot, -e]`
+    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    pub terminals: HashMap<String, Vec<String>>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             desktop_exec: DEFAULT_DESKTOP_EXEC.to_string(),
+            existing_windows: ExistingWindows::default(),
+            on_failure: OnFailure::default(),
+            terminals: HashMap::new(),
         }
     }
 }
@@ -65,11 +103,13 @@ pub fn get_tree_path(
         Compositor::Sway => "",
         Compositor::Niri => "niri",
     };
-    let file_path_str = &format!(
-        "{sub_dir}/{}.yaml",
+    // an empty `sub_dir` must not give an absolute `/<name>.yaml` path
+    let file_path = Path::new(sub_dir).join(format!(
+        "{}.yaml",
         tree_name.unwrap_or("default".to_owned())
-    );
-    base_dirs.place_config_file(file_path_str).context(format!(
-        "failed to access config file path: {file_path_str}"
+    ));
+    base_dirs.place_config_file(&file_path).context(format!(
+        "failed to access config file path: {}",
+        file_path.display()
     ))
 }
@@ -1,16 +1,19 @@
 mod args;
+mod backend;
 mod config;
 mod consts;
 mod models;
 mod niri;
+mod reconcile;
 mod sway;
 mod util;
 
 use anyhow::{Context, Ok, Result};
 use args::{Args, Mode};
+use backend::Backend;
 use clap::Parser;
-use models::{Compositor, load_tree, save_tree};
-use tracing::{error, level_filters::LevelFilter, warn};
+use models::Compositor;
+use tracing::{error, level_filters::LevelFilter};
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
 use xdg::BaseDirectories;
@@ -48,47 +51,34 @@ fn run() -> Result<()> {
         config_file_path.display()
     ))?;
 
-    // TODO: make sway branch reuse get_tree_path() function
-    // TODO: make sway branch reuse save_tree() function
-    if options.compositor == Compositor::Sway {
-        let tree_file = options.name.unwrap_or("default".to_owned()) + ".yaml";
-        let tree_file_path = base_dirs
-            .place_config_file(&tree_file)
-            .context(format!("failed to access tree file path: {tree_file}"))?;
-
-        return match options.mode {
-            Mode::Save => sway::save_tree(&tree_file_path, options.dry_run)
-                .context(format!("failed to save tree: {}", tree_file_path.display())),
-            Mode::Load { workspace } => sway::load_tree(
-                &config,
-                &tree_file_path,
-                options.dry_run,
-                options.no_kill,
-                workspace,
-            )
-            .context(format!("failed to load tree: {}", tree_file_path.display())),
-        };
-    }
-
-    // Niri branch
+    let adopt = backend::adopts(options.compositor, &config, options.no_kill);
     let tree_path = config::get_tree_path(base_dirs, options.compositor, options.name)?;
-    let mut n = niri::Niri::new(config, options.dry_run)?;
+    let mut backend: Box<dyn Backend> = match options.compositor {
+        Compositor::Sway => Box::new(
+            sway::Sway::new(config, options.dry_run, options.no_kill)
+                .context("on sway::Sway::new()")?,
+        ),
+        Compositor::Niri => Box::new(
+            niri::Niri::new(config, options.dry_run, options.no_kill)
+                .context("on niri::Niri::new()")?,
+        ),
+    };
 
     match options.mode {
-        Mode::Save => {
-            let tree = n.get_tree().context("on niri::Niri::get_tree()")?;
-            save_tree(&tree_path, &tree).context("on save_tree()")
-        }
-        Mode::Load { workspace } => {
-            if let Some(ws) = &workspace {
-                warn!(
-                    "loading a specific workspace is incompatible with Niri, ignoring it (trying to load {ws})"
-                );
-            }
-            n.clear().context("on n.clear()")?;
-            let tree = load_tree(&tree_path).context("on load_tree()")?;
-            n.load_tree(&tree).context("on n.load_tree()")
-        }
+        Mode::Save => backend::save(backend.as_mut(), &tree_path, options.dry_run)
+            .context(format!("failed to save tree: {}", tree_path.display())),
+        Mode::Load {
+            workspace,
+            reconcile,
+            parallel,
+        } => backend::load(
+            backend.as_mut(),
+            &tree_path,
+            workspace.as_deref(),
+            reconcile || adopt,
+            parallel,
+        )
+        .context(format!("failed to load tree: {}", tree_path.display())),
     }
 }
 
@@ -1,6 +1,13 @@
-use std::{fmt::Display, fs, path::Path, str::FromStr, time::Duration};
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_yaml::to_string;
 
@@ -33,7 +40,6 @@ impl FromStr for Compositor {
     }
 }
 
-// TODO: use this in sway side
 pub fn save_tree(tree_path: &Path, tree: &Vec<Node>) -> Result<()> {
     let serialized_yaml = to_string(&tree).context("on to_string()")?;
     fs::write(tree_path, serialized_yaml)
@@ -42,11 +48,13 @@ pub fn save_tree(tree_path: &Path, tree: &Vec<Node>) -> Result<()> {
     Ok(())
 }
 
-// TODO: use this in sway side
 pub fn load_tree(tree_path: &Path) -> Result<Vec<Node>> {
     let file_content = fs::read_to_string(tree_path).context("on fs::read_to_string()")?;
-    let tree: Vec<Node> =
+    let mut tree: Vec<Node> =
         serde_yaml::from_str(&file_content).context("on serde_yaml::from_str()")?;
+    for node in tree.iter_mut() {
+        node.compile_matchers().context("on compile_matchers()")?;
+    }
     Ok(tree)
 }
 
@@ -94,24 +102,44 @@ pub enum NodeLayout {
 }
 
 impl NodeType {
-    fn is_window(&self) -> bool {
-        matches!(self, NodeType::Con | NodeType::FloatingCon)
+    fn is_con(&self) -> bool {
+        matches!(self, NodeType::Con)
     }
 }
 
 impl NodeLayout {
-    fn is_none(&self) -> bool {
+    pub fn is_none(&self) -> bool {
         matches!(self, NodeLayout::Unknown | NodeLayout::None)
     }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct Node {
+    /// Id of the live window in the compositor, never saved.
+    #[serde(skip)]
+    pub id: Option<i64>,
+    /// Pid of the process of the live window, never saved.
+    #[serde(skip)]
+    pub pid: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    /// Output of a workspace.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Output>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub app_id: Option<String>,
-    #[serde(rename = "type", skip_serializing_if = "NodeType::is_window", default)]
+    /// X11 class of a XWayland window, which has no app_id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    /// X11 instance of a XWayland window.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Picks the window of this node, when its app_id or class is not enough to tell it apart.
+    #[serde(rename = "match", skip_serializing_if = "Option::is_none")]
+    pub matcher: Option<Match>,
+    #[serde(rename = "type", skip_serializing_if = "NodeType::is_con", default)]
     pub node_type: NodeType,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub nodes: Vec<Node>,
@@ -119,10 +147,19 @@ pub struct Node {
     pub fullscreen_mode: Option<u8>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub percent: Option<f64>,
+    /// Geometry of a floating window, relative to its output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rect: Option<Rect>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub desktop_entry: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub exec: Option<String>,
+    pub exec: Option<Exec>,
+    /// Working directory the app was in, and is started in again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    /// Session a terminal was attached to, and is attached to again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<Session>,
     #[serde(skip_serializing_if = "NodeLayout::is_none", default)]
     pub layout: NodeLayout,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -135,6 +172,433 @@ pub struct Node {
     pub timeout: Option<Duration>,
 }
 
+impl Node {
+    /// Whether a window can be told apart as the one of this node.
+    pub fn is_identifiable(&self) -> bool {
+        self.matcher.is_some() || self.app_id.is_some() || self.class.is_some()
+    }
+
+    /// Whether the live window `window` is the one of this saved node.
+    ///
+    /// The `match` spec decides when given, otherwise the app_id must be the same,
+    /// or for a XWayland window, the class and the instance.
+    pub fn matches(&self, window: &Node) -> bool {
+        if let Some(matcher) = &self.matcher {
+            return matcher.matches(window);
+        }
+        if self.app_id.is_some() {
+            return self.app_id == window.app_id;
+        }
+        self.class.is_some()
+            && self.class == window.class
+            && (self.instance.is_none() || self.instance == window.instance)
+    }
+
+    /// Compiles the `match` specs of this node and its children, failing on the first invalid
+    /// regex.
+    fn compile_matchers(&mut self) -> Result<()> {
+        if let Some(matcher) = &mut self.matcher {
+            matcher.compile()?;
+        }
+        self.nodes.iter_mut().try_for_each(Node::compile_matchers)
+    }
+}
+
+/// Regexes picking the window of a node, all the given ones must match.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Match {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The regexes above, compiled when the tree is loaded.
+    #[serde(skip)]
+    pub compiled: Compiled,
+}
+
+impl Match {
+    /// Compiles the regexes once, failing on the first invalid one.
+    fn compile(&mut self) -> Result<()> {
+        let compile = |re: &Option<String>| {
+            re.as_deref()
+                .map(|re| Regex::new(re).context(format!("invalid match regex {re:?}")))
+                .transpose()
+        };
+        self.compiled = Compiled(Some([
+            compile(&self.app_id)?,
+            compile(&self.class)?,
+            compile(&self.instance)?,
+            compile(&self.title)?,
+        ]));
+        Ok(())
+    }
+
+    fn matches(&self, window: &Node) -> bool {
+        let Compiled(Some(regexes)) = &self.compiled else {
+            // built rather than loaded from a tree file
+            let mut matcher = self.clone();
+            return matcher.compile().is_ok() && matcher.matches(window);
+        };
+        let values = [
+            &window.app_id,
+            &window.class,
+            &window.instance,
+            &window.title,
+        ];
+        regexes
+            .iter()
+            .zip(values)
+            .all(|(re, value)| match (re, value) {
+                (None, _) => true,
+                (Some(re), Some(value)) => re.is_match(value),
+                (Some(_), None) => false,
+            })
+    }
+}
+
+/// Two specs are the same when their regexes are, compiled or not.
+impl PartialEq for Match {
+    fn eq(&self, other: &Self) -> bool {
+        self.app_id == other.app_id
+            && self.class == other.class
+            && self.instance == other.instance
+            && self.title == other.title
+    }
+}
+
+/// The regexes of a `Match`, app_id, class, instance then title, once compiled.
+#[derive(Debug, Clone, Default)]
+pub struct Compiled(Option<[Option<Regex>; 4]>);
+
+/// The command starting the app of a node.
+///
+/// A string is a `sh -c` script, as written by hand. A list is the exact arguments of the
+/// process, as saved from `/proc`, started without a shell so nothing in them is re-parsed.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Exec {
+    Shell(String),
+    Argv(Vec<String>),
+}
+
+/// The script itself, or the arguments quoted for a shell.
+impl Display for Exec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Exec::Shell(script) => write!(f, "{script}"),
+            Exec::Argv(args) => {
+                let quoted: Vec<_> = args.iter().map(|arg| shell_quote(arg)).collect();
+                write!(f, "{}", quoted.join(" "))
+            }
+        }
+    }
+}
+
+/// A terminal multiplexer, whose sessions outlive the terminals showing them.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Multiplexer {
+    Tmux,
+    Zellij,
+}
+
+impl Multiplexer {
+    /// The multiplexer `args` are the command line of a client of, by its program name.
+    pub fn of_client(args: &[String]) -> Option<Self> {
+        let program = Path::new(args.first()?).file_name()?;
+        match program.to_str()? {
+            "tmux" => Some(Multiplexer::Tmux),
+            "zellij" => Some(Multiplexer::Zellij),
+            _ => None,
+        }
+    }
+
+    /// The session named on the command line `args` of a client, as in `tmux attach -t work`
+    /// or `zellij attach work`.
+    pub fn session_arg(self, args: &[String]) -> Option<String> {
+        let flags: &[&str] = match self {
+            Multiplexer::Tmux => &["-t", "-s"],
+            Multiplexer::Zellij => &["-s", "--session"],
+        };
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            let name = if flags.contains(&arg.as_str()) {
+                args.next().cloned()
+            } else if let Some(name) = flags
+                .iter()
+                .find_map(|flag| arg.strip_prefix(flag).filter(|_| arg.len() > 2))
+            {
+                Some(name.trim_start_matches('=').to_string())
+            } else if self == Multiplexer::Zellij && matches!(arg.as_str(), "attach" | "a") {
+                args.find(|arg| !arg.starts_with('-')).cloned()
+            } else {
+                continue;
+            };
+            // a tmux target may name a window and a pane after the session
+            return name.map(|name| match self {
+                Multiplexer::Tmux => name
+                    .split([':', '.'])
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+                Multiplexer::Zellij => name,
+            });
+        }
+        None
+    }
+
+    /// The options on the command line `args` of a client choosing the server it talks to, as
+    /// `-L work` or `-S /tmp/work.sock` for tmux. Zellij has a single server per user.
+    pub fn server_args(self, args: &[String]) -> Vec<String> {
+        let mut server = vec![];
+        if self == Multiplexer::Zellij {
+            return server;
+        }
+        // global options come before the command, some of them take a value
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-L" | "-S" => {
+                    server.extend([Some(arg), args.next()].into_iter().flatten().cloned())
+                }
+                "-c" | "-f" | "-T" => {
+                    args.next();
+                }
+                _ if arg.starts_with("-L") || arg.starts_with("-S") => server.push(arg.clone()),
+                _ if arg.starts_with('-') => {}
+                _ => break,
+            }
+        }
+        server
+    }
+}
+
+/// A tmux or zellij session, as shown in a terminal.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Session {
+    pub multiplexer: Multiplexer,
+    pub name: String,
+    /// The options of the client choosing its server, as `[-L, work]` for a tmux session living
+    /// on the `work` socket.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub server: Vec<String>,
+}
+
+impl Session {
+    /// The command attaching to this session, creating it when it does not exist.
+    pub fn attach_command(&self) -> Vec<String> {
+        let (program, command): (&str, &[&str]) = match self.multiplexer {
+            Multiplexer::Tmux => ("tmux", &["new-session", "-A", "-s"]),
+            Multiplexer::Zellij => ("zellij", &["attach", "--create"]),
+        };
+        [program.to_string()]
+            .into_iter()
+            .chain(self.server.iter().cloned())
+            .chain(command.iter().map(|arg| arg.to_string()))
+            .chain([self.name.clone()])
+            .collect()
+    }
+}
+
+fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-+=@%:,./".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// A monitor, known by its connector name and, when available, by what is plugged into it.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Output {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub make: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+}
+
+impl Output {
+    /// Finds this output among the `live` ones.
+    ///
+    /// The monitor is looked up by make, model and serial first, so it is found back when
+    /// plugged into another connector, then by connector name.
+    pub fn find_in<'a>(&self, live: &'a [Output]) -> Option<&'a Output> {
+        let same_monitor: Vec<_> = live
+            .iter()
+            .filter(|output| {
+                self.serial.is_some()
+                    && output.make == self.make
+                    && output.model == self.model
+                    && output.serial == self.serial
+            })
+            .collect();
+        match same_monitor.as_slice() {
+            [output] => Some(output),
+            // twin monitors without serial, the connector tells them apart
+            _ => live.iter().find(|output| output.name == self.name),
+        }
+    }
+}
+
 fn none_or_zero_u8(opt: &Option<u8>) -> bool {
     matches!(opt, None | Some(0))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_exec_as_a_script_or_arguments() {
+        let tree: Vec<Node> = serde_yaml::from_str(
+            "- exec: foot -e htop\n- exec: [foot, -e, nvim, ~/My Notes/todo.md]\n",
+        )
+        .unwrap();
+
+        assert_eq!(tree[0].exec, Some(Exec::Shell("foot -e htop".to_string())));
+        let args = ["foot", "-e", "nvim", "~/My Notes/todo.md"];
+        assert_eq!(
+            tree[1].exec,
+            Some(Exec::Argv(args.iter().map(|arg| arg.to_string()).collect()))
+        );
+    }
+
+    #[test]
+    fn compiles_match_regexes_when_loading() {
+        let tree_path = std::env::temp_dir().join(format!("tree-{}.yaml", std::process::id()));
+        let load = |yaml: &str| {
+            fs::write(&tree_path, yaml).unwrap();
+            load_tree(&tree_path)
+        };
+
+        let tree = load("- nodes:\n  - match:\n      title: ' - Mail$'\n").unwrap();
+        let matcher = tree[0].nodes[0].matcher.as_ref().unwrap();
+        assert!(matches!(
+            &matcher.compiled,
+            Compiled(Some([None, None, None, Some(_)]))
+        ));
+        let window = |title: &str| Node {
+            title: Some(title.to_string()),
+            ..Default::default()
+        };
+        assert!(tree[0].nodes[0].matches(&window("Inbox - Mail")));
+        assert!(!tree[0].nodes[0].matches(&window("Mail - Firefox")));
+
+        let built = Match {
+            title: Some(" - Mail$".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(matcher, &built);
+        assert_ne!(
+            matcher,
+            &Match {
+                title: Some(" - Chat$".to_string()),
+                ..Default::default()
+            }
+        );
+
+        assert!(load("- match:\n    title: '(unclosed'\n").is_err());
+        fs::remove_file(&tree_path).unwrap();
+    }
+
+    #[test]
+    fn quotes_arguments_for_a_shell() {
+        let exec = Exec::Argv(
+            ["nvim", "My Notes/it's.md", "-c", "", "set nu"]
+                .map(String::from)
+                .to_vec(),
+        );
+
+        assert_eq!(
+            exec.to_string(),
+            r#"nvim 'My Notes/it'\''s.md' -c '' 'set nu'"#
+        );
+        assert_eq!(
+            Exec::Shell("foot -e htop".to_string()).to_string(),
+            "foot -e htop"
+        );
+    }
+
+    #[test]
+    fn finds_the_session_of_multiplexer_clients() {
+        let session = |cmdline: &str| {
+            let args: Vec<_> = cmdline.split(' ').map(String::from).collect();
+            let multiplexer = Multiplexer::of_client(&args)?;
+            Some((multiplexer, multiplexer.session_arg(&args)))
+        };
+
+        let work = Some("work".to_string());
+        let tmux = |name| Some((Multiplexer::Tmux, name));
+        let zellij = |name| Some((Multiplexer::Zellij, name));
+        assert_eq!(session("tmux attach -t work"), tmux(work.clone()));
+        assert_eq!(session("/usr/bin/tmux new -A -swork"), tmux(work.clone()));
+        assert_eq!(session("tmux a -t work:2.1"), tmux(work.clone()));
+        assert_eq!(session("tmux"), tmux(None));
+        assert_eq!(session("zellij attach --create work"), zellij(work.clone()));
+        assert_eq!(session("zellij --session=work"), zellij(work.clone()));
+        assert_eq!(session("zellij -l compact"), zellij(None));
+        assert_eq!(session("foot -e tmux"), None);
+    }
+
+    #[test]
+    fn keeps_the_server_of_tmux_clients() {
+        let server = |multiplexer: Multiplexer, cmdline: &str| {
+            let args: Vec<_> = cmdline.split(' ').map(String::from).collect();
+            multiplexer.server_args(&args)
+        };
+
+        let tmux = Multiplexer::Tmux;
+        assert_eq!(server(tmux, "tmux -L work attach -t dev"), ["-L", "work"]);
+        assert_eq!(
+            server(tmux, "tmux -f tmux.conf -S/tmp/work.sock -2 a"),
+            ["-S/tmp/work.sock"]
+        );
+        assert!(server(tmux, "tmux attach -t dev -L work").is_empty());
+        assert!(server(tmux, "tmux").is_empty());
+        assert!(server(Multiplexer::Zellij, "zellij -L work").is_empty());
+    }
+
+    #[test]
+    fn attaches_to_sessions_or_creates_them() {
+        let node: Node =
+            serde_yaml::from_str("session: {multiplexer: zellij, name: work}").unwrap();
+        let session = node.session.unwrap();
+
+        assert_eq!(
+            session.attach_command(),
+            ["zellij", "attach", "--create", "work"]
+        );
+        let tmux = Session {
+            multiplexer: Multiplexer::Tmux,
+            ..session
+        };
+        assert_eq!(
+            tmux.attach_command(),
+            ["tmux", "new-session", "-A", "-s", "work"]
+        );
+        let on_socket = Session {
+            server: vec!["-L".to_string(), "dev".to_string()],
+            ..tmux
+        };
+        assert_eq!(
+            on_socket.attach_command(),
+            ["tmux", "-L", "dev", "new-session", "-A", "-s", "work"]
+        );
+    }
+}
@@ -1,49 +1,283 @@
 use std::{
-    collections::HashMap,
-    thread,
-    time::{Duration, Instant},
+    collections::{HashMap, HashSet, VecDeque},
+    env,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    os::unix::net::UnixStream,
+    time::Instant,
     vec,
 };
 
-// TODO: I do not think Niri expose the columns, so this is not possible to see how windows are arranged
-// TODO: same for columns widths then
+// Niri IPC does not expose the columns: they are found by walking the focus through them
+// (see `walk_columns`). Nor the size of the windows, so the width of a column can not be saved,
+// nor found by walking: it is only restored when its `percent` is set by hand in the tree file.
 use anyhow::{Context, Result, anyhow, bail};
 use tracing::{debug, info, warn};
 
+#[cfg(test)]
+mod fake;
+
 use crate::{
+    backend::{self, Backend, Spawner},
     config::Config,
-    consts::MAX_WAIT_DURATION,
-    models::{Node, NodeLayout, NodeType},
+    models::{Exec, Node, NodeLayout, NodeType, Output},
+    util::{extract_cmdline, extract_cwd},
 };
 
 pub struct Niri {
     socket: niri_ipc::socket::Socket,
     cfg: Config,
     dry_run: bool,
+    no_kill: bool,
+    spawner: Spawner,
 }
 
-// TODO: make it a trait?
 impl Niri {
-    pub fn new(cfg: Config, dry_run: bool) -> Result<Self> {
+    pub fn new(cfg: Config, dry_run: bool, no_kill: bool) -> Result<Self> {
         let socket = niri_ipc::socket::Socket::connect().context("on Socket::connect()")?;
         Ok(Self {
             socket,
             cfg,
             dry_run,
+            no_kill,
+            spawner: Spawner::new(dry_run),
         })
     }
 
-    pub fn get_tree(&mut self) -> Result<Vec<Node>> {
-        // get workspaces
+    fn fetch_windows(&mut self) -> Result<Vec<niri_ipc::Window>> {
+        let response = self
+            .send(niri_ipc::Request::Windows)
+            .context("on self.send(windows)")?;
+
+        match response {
+            niri_ipc::Response::Windows(windows) => Ok(windows),
+            _ => bail!("unexpected response type from Niri, expected Windows"),
+        }
+    }
+
+    fn fetch_workspaces(&mut self) -> Result<Vec<niri_ipc::Workspace>> {
         let reply = self
             .socket
             .send(niri_ipc::Request::Workspaces)
-            .context("on socket.send()")?
-            .map_err(|e: String| anyhow!("on decoding Niri answer: {:?}", e))?;
+            .context("on socket.send(workspaces)")?
+            .map_err(|e| anyhow!("on decoding Niri answer: {:?}", e))?;
+
+        match reply {
+            niri_ipc::Response::Workspaces(workspaces) => Ok(workspaces),
+            _ => bail!("unexpected response type from Niri, expected Workspaces"),
+        }
+    }
+
+    /// The enabled outputs.
+    fn fetch_outputs(&mut self) -> Result<Vec<Output>> {
+        let reply = self
+            .socket
+            .send(niri_ipc::Request::Outputs)
+            .context("on socket.send(outputs)")?
+            .map_err(|e| anyhow!("on decoding Niri answer: {:?}", e))?;
+
+        let niri_ipc::Response::Outputs(outputs) = reply else {
+            bail!("unexpected response type from Niri, expected Outputs");
+        };
+
+        let mut outputs: Vec<_> = outputs
+            .into_values()
+            .filter(|output| output.logical.is_some())
+            .map(|output| Output {
+                name: output.name,
+                make: Some(output.make),
+                model: Some(output.model),
+                serial: output.serial,
+            })
+            .collect();
+        outputs.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(outputs)
+    }
+
+    /// Sends an action even in dry run, for the ones only moving the focus around.
+    fn focus(&mut self, action: niri_ipc::Action) -> Result<()> {
+        let _ = self
+            .socket
+            .send(niri_ipc::Request::Action(action))
+            .context("on socket.send(action)")?
+            .map_err(|e| anyhow!("on decoding Niri answer: {:?}", e))?;
+        Ok(())
+    }
 
-        let niri_ipc::Response::Workspaces(workspaces) = reply else {
-            return Err(anyhow!("Unexpected response type from Niri"));
+    fn focused_window(&mut self) -> Result<Option<u64>> {
+        let reply = self
+            .socket
+            .send(niri_ipc::Request::FocusedWindow)
+            .context("on socket.send(focused window)")?
+            .map_err(|e| anyhow!("on decoding Niri answer: {:?}", e))?;
+
+        let niri_ipc::Response::FocusedWindow(window) = reply else {
+            bail!("unexpected response type from Niri, expected FocusedWindow");
         };
+        Ok(window.map(|window| window.id))
+    }
+
+    /// Returns the columns of the workspace with the given id, left to right,
+    /// as the ids of their windows, top to bottom.
+    ///
+    /// Niri does not tell which windows share a column, so we focus the first column and
+    /// walk it down, then the next one, until the focus does not move anymore.
+    fn walk_columns(&mut self, workspace_id: u64) -> Result<Vec<Vec<u64>>> {
+        self.focus(niri_ipc::Action::FocusWorkspace {
+            reference: niri_ipc::WorkspaceReferenceArg::Id(workspace_id),
+        })?;
+        self.focus(niri_ipc::Action::FocusColumnFirst {})?;
+
+        let mut columns: Vec<Vec<u64>> = vec![];
+        loop {
+            self.focus(niri_ipc::Action::FocusWindowTop {})?;
+            let Some(top) = self.focused_window()? else {
+                break;
+            };
+            if columns.iter().flatten().any(|&id| id == top) {
+                // the last column, `FocusColumnRight` did not move
+                break;
+            }
+
+            let mut column = vec![top];
+            loop {
+                self.focus(niri_ipc::Action::FocusWindowDown {})?;
+                match self.focused_window()? {
+                    Some(id) if !column.contains(&id) => column.push(id),
+                    _ => break,
+                }
+            }
+            debug!("column: {column:?}");
+            columns.push(column);
+
+            self.focus(niri_ipc::Action::FocusColumnRight {})?;
+        }
+
+        Ok(columns)
+    }
+
+    /// Focuses the live window with the given id, so the next spawned window lands next to it.
+    fn focus_window(&mut self, id: i64) -> Result<()> {
+        debug!("focusing window: {id}");
+        let _ = self
+            .send(niri_ipc::Request::Action(niri_ipc::Action::FocusWindow {
+                id: id as u64,
+            }))
+            .context(format!("on FocusWindow for id: {id}"))?;
+        Ok(())
+    }
+
+    fn send(&mut self, request: niri_ipc::Request) -> Result<niri_ipc::Response> {
+        if self.dry_run {
+            info!("dry run mode, not sending request: {:?}", request);
+            return Ok(niri_ipc::Response::Handled);
+        }
+        self.socket
+            .send(request)
+            .context("on socket.send()")?
+            .map_err(|e| anyhow!("on decoding Niri answer: {:?}", e))
+    }
+}
+
+/// The niri event stream, giving the windows as they open or change.
+struct WindowEvents {
+    // `niri_ipc::socket::Socket` hides its stream, we need it for the read timeout
+    reader: BufReader<UnixStream>,
+    /// The windows open before listening, never given.
+    before: HashSet<u64>,
+    /// The windows of the last event, not given yet.
+    pending: VecDeque<Node>,
+}
+
+impl WindowEvents {
+    fn open(before: HashSet<u64>) -> Result<Self> {
+        let socket_path = env::var_os(niri_ipc::socket::SOCKET_PATH_ENV)
+            .context("NIRI_SOCKET is not set, are you running this within niri?")?;
+        let stream = UnixStream::connect(socket_path).context("on UnixStream::connect()")?;
+        let mut reader = BufReader::new(stream);
+
+        let mut line = serde_json::to_string(&niri_ipc::Request::EventStream)
+            .context("on serde_json::to_string()")?;
+        line.push('\n');
+        reader
+            .get_mut()
+            .write_all(line.as_bytes())
+            .context("on write_all(event stream)")?;
+        line.clear();
+        reader.read_line(&mut line).context("on read_line()")?;
+        let reply: niri_ipc::Reply =
+            serde_json::from_str(&line).context("on serde_json::from_str()")?;
+        reply.map_err(|e| anyhow!("on decoding Niri answer: {:?}", e))?;
+
+        Ok(Self {
+            reader,
+            before,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// The windows of the next window event, `None` once `deadline` is passed.
+    ///
+    /// The stream starts with all the current windows, so one opened before it is still seen,
+    /// and a window is given again on each change.
+    fn next_windows(&mut self, deadline: Option<Instant>) -> Result<Option<Vec<niri_ipc::Window>>> {
+        let mut line = String::new();
+        loop {
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => Some(remaining),
+                    _ => return Ok(None),
+                },
+                None => None,
+            };
+            self.reader
+                .get_ref()
+                .set_read_timeout(remaining)
+                .context("on set_read_timeout()")?;
+
+            line.clear();
+            match self.reader.read_line(&mut line) {
+                Ok(0) => bail!("niri closed the event stream"),
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e).context("on read_line()"),
+            }
+
+            // events unknown to our niri-ipc version are skipped as well
+            match serde_json::from_str(&line) {
+                Ok(niri_ipc::Event::WindowsChanged { windows }) => return Ok(Some(windows)),
+                Ok(niri_ipc::Event::WindowOpenedOrChanged { window }) => {
+                    return Ok(Some(vec![window]));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl backend::WindowEvents for WindowEvents {
+    fn next_window(&mut self, deadline: Option<Instant>) -> Result<Option<Node>> {
+        loop {
+            if let Some(window) = self.pending.pop_front() {
+                return Ok(Some(window));
+            }
+            let Some(windows) = self.next_windows(deadline)? else {
+                return Ok(None);
+            };
+            self.pending.extend(
+                windows
+                    .iter()
+                    .filter(|window| !self.before.contains(&window.id))
+                    .map(identity),
+            );
+        }
+    }
+}
+
+impl Backend for Niri {
+    fn get_tree(&mut self) -> Result<Vec<Node>> {
+        let workspaces = self.fetch_workspaces().context("on fetch_workspaces()")?;
 
         // get windows and map them to workspaces
         let reply = self
@@ -56,11 +290,40 @@ impl Niri {
             return Err(anyhow!("unexpected response type from Niri"));
         };
 
-        build_tree(workspaces, windows)
+        let outputs = self.fetch_outputs().context("on fetch_outputs()")?;
+
+        // walking the columns moves the focus, it is put back once done
+        let focused = self.focused_window().context("on focused_window()")?;
+        let focused_workspace = workspaces.iter().find(|ws| ws.is_focused).map(|ws| ws.id);
+        let mut columns = vec![];
+        for workspace in workspaces.iter() {
+            if windows
+                .iter()
+                .any(|w| w.workspace_id == Some(workspace.id) && !w.is_floating)
+            {
+                columns.extend(
+                    self.walk_columns(workspace.id)
+                        .context(format!("on walk_columns({})", workspace.id))?,
+                );
+            }
+        }
+        if let Some(id) = focused {
+            self.focus(niri_ipc::Action::FocusWindow { id })?;
+        } else if let Some(id) = focused_workspace {
+            self.focus(niri_ipc::Action::FocusWorkspace {
+                reference: niri_ipc::WorkspaceReferenceArg::Id(id),
+            })?;
+        }
+
+        let mut tree = build_tree(workspaces, windows, &outputs, &columns)?;
+        for workspace in tree.iter_mut() {
+            backend::capture_terminals(&self.cfg, workspace);
+        }
+        Ok(tree)
     }
 
-    /// Clears all current workspaces and closes all current windows.
-    pub fn clear(&mut self) -> Result<()> {
+    /// Closes the windows of the live workspace named `workspace`, or all current windows.
+    fn clear(&mut self, workspace: Option<&str>) -> Result<()> {
         // transition
         // we should make it configurable, and not for now we are cheating by recalling the screen transition with 200ms delay to override this one if we finish early
         let _ = self
@@ -71,86 +334,164 @@ impl Niri {
             ))
             .context("on Action::Transition(Clear)")?;
 
-        let windows = self.fetch_windows().context("on fetch_windows()")?;
+        let mut windows = self.fetch_windows().context("on fetch_windows()")?;
+        if let Some(name) = workspace {
+            let workspaces = self.fetch_workspaces().context("on fetch_workspaces()")?;
+            let id = workspaces
+                .iter()
+                .find(|ws| ws.name.as_deref() == Some(name))
+                .map(|ws| ws.id);
+            windows.retain(|window| id.is_some() && window.workspace_id == id);
+        }
 
         for window in windows {
-            debug!("closing window: {window:?}");
-            let _ = self
-                .send(niri_ipc::Request::Action(niri_ipc::Action::CloseWindow {
-                    id: Some(window.id),
-                }))
-                .context(format!("on CloseWindow for id: {}", window.id))?;
+            self.close_window(window.id as i64)?;
         }
         Ok(())
     }
 
-    pub fn load_tree(&mut self, tree: &[Node]) -> Result<()> {
-        for (idx, node) in tree.iter().enumerate() {
-            if !matches!(node.node_type, NodeType::Root | NodeType::Workspace) {
-                warn!(
-                    "skipping: uncompatible node at idx={idx}, looking for Workspace: {}",
-                    node.node_type
-                );
-                continue;
+    fn close_window(&mut self, id: i64) -> Result<()> {
+        if self.no_kill {
+            info!("no kill mode, not closing window: {id}");
+            return Ok(());
+        }
+        debug!("closing window: {id}");
+        let _ = self
+            .send(niri_ipc::Request::Action(niri_ipc::Action::CloseWindow {
+                id: Some(id as u64),
+            }))
+            .context(format!("on CloseWindow for id: {id}"))?;
+        Ok(())
+    }
+
+    fn focus_workspace(&mut self, node: &Node) -> Result<()> {
+        let output = match &node.output {
+            Some(saved) => {
+                let outputs = self.fetch_outputs().context("on fetch_outputs()")?;
+                let output = saved.find_in(&outputs).map(|output| output.name.clone());
+                if output.is_none() {
+                    warn!(
+                        "output {} not found, loading workspace {:?} on the focused one",
+                        saved.name, node.name
+                    );
+                }
+                output
             }
+            None => None,
+        };
 
-            let ref_workspace = niri_ipc::WorkspaceReferenceArg::Index((idx + 1) as u8);
+        if let Some(output) = &output {
+            debug!("focusing output: {output}");
+            let _ = self.send(niri_ipc::Request::Action(niri_ipc::Action::FocusMonitor {
+                output: output.clone(),
+            }))?;
+        }
 
-            // name the workspace if it has a name
-            if let Some(name) = &node.name {
-                debug!("setting workspace name: {name}");
-                let _ = self.send(niri_ipc::Request::Action(
-                    niri_ipc::Action::SetWorkspaceName {
-                        name: name.to_string(),
-                        workspace: Some(ref_workspace.clone()),
-                    },
-                ))?;
+        // a workspace with the same name is reused, otherwise the unnamed empty workspace
+        // ending the focused output is named: named workspaces, like the ones declared in
+        // the niri config, are never renamed
+        let workspaces = self.fetch_workspaces().context("on fetch_workspaces()")?;
+        let existing = node
+            .name
+            .as_ref()
+            .filter(|name| workspaces.iter().any(|ws| ws.name.as_ref() == Some(*name)));
+        let ref_workspace = match existing {
+            Some(name) => niri_ipc::WorkspaceReferenceArg::Name(name.clone()),
+            None => {
+                let focused_output = workspaces
+                    .iter()
+                    .find(|ws| ws.is_focused)
+                    .and_then(|ws| ws.output.clone());
+                let last = workspaces
+                    .iter()
+                    .filter(|ws| ws.output == focused_output && ws.name.is_none())
+                    .max_by_key(|ws| ws.idx)
+                    .context("no unnamed workspace on the focused output")?;
+                let ref_workspace = niri_ipc::WorkspaceReferenceArg::Id(last.id);
+
+                // name the workspace if it has a name
+                if let Some(name) = &node.name {
+                    debug!("setting workspace name: {name}");
+                    let _ = self.send(niri_ipc::Request::Action(
+                        niri_ipc::Action::SetWorkspaceName {
+                            name: name.to_string(),
+                            workspace: Some(ref_workspace.clone()),
+                        },
+                    ))?;
+                }
+                ref_workspace
             }
+        };
+
+        // move current view to the workspace
+        debug!("focusing workspace: {:?}", ref_workspace);
+        let _ = self.send(niri_ipc::Request::Action(
+            niri_ipc::Action::FocusWorkspace {
+                reference: ref_workspace,
+            },
+        ))?;
 
-            // move current view to the workspace
-            debug!("focusing workspace: {:?}", ref_workspace);
+        // a named workspace may already live on another output
+        if let Some(output) = output {
+            debug!("moving workspace to output: {output}");
             let _ = self.send(niri_ipc::Request::Action(
-                niri_ipc::Action::FocusWorkspace {
-                    reference: ref_workspace,
+                niri_ipc::Action::MoveWorkspaceToMonitor {
+                    output,
+                    reference: None,
                 },
             ))?;
+        }
 
-            // now it should be split containers, informations we do not have in Niri yet
-            for (idx, node) in node.nodes.iter().enumerate() {
-                if !matches!(
-                    node.node_type,
-                    NodeType::Con | NodeType::FloatingCon | NodeType::Unknown
-                ) {
-                    warn!(
-                        "skipping: uncompatible node at idx={idx}, looking for Con(tainer): {}",
-                        node.node_type
-                    );
+        Ok(())
+    }
+
+    /// Spawns the windows column by column: every window opens in a new column on the right,
+    /// the next ones of the same column are consumed into it.
+    fn load_workspace(&mut self, node: &Node) -> Result<()> {
+        let mut columns = vec![];
+        collect_columns(node, &mut columns);
+
+        for (width, windows) in columns {
+            // until a window of the column is there, the next one starts the column
+            let mut started = false;
+            for window in windows {
+                if let Some(id) = window.id {
+                    self.focus_window(id)
+                        .context(format!("on focus_window({id})"))?;
+                    started = true;
                     continue;
                 }
-
-                // spawn the applications (windows)
-                for (idx, node) in node.nodes.iter().enumerate() {
-                    if !matches!(
-                        node.node_type,
-                        NodeType::Con | NodeType::FloatingCon | NodeType::Unknown
-                    ) {
-                        warn!(
-                            "skipping: uncompatible node at idx={idx}, looking for Con(tainer): {}",
-                            node.node_type
-                        );
-                        continue;
-                    }
-
-                    debug!(
-                        "spawning application: {:?}",
-                        node.app_id.as_ref().unwrap_or(&"unknown".to_string())
-                    );
-                    self.spawn_and_wait(node)
-                        .context(format!("on spawn_and_wait for node: {node:?}"))?;
+                let Some(id) = self
+                    .spawn_and_wait(window)
+                    .context(format!("on spawn_and_wait for node: {window:?}"))?
+                else {
+                    continue;
+                };
+                if started {
+                    debug!("consuming window {id} into the column on its left");
+                    let _ = self.send(niri_ipc::Request::Action(
+                        niri_ipc::Action::ConsumeOrExpelWindowLeft {
+                            id: Some(id as u64),
+                        },
+                    ))?;
                 }
+                started = true;
+            }
+
+            // the column is the focused one once one of its windows is there
+            if let (Some(width), true) = (width, started) {
+                debug!("setting column width: {width}");
+                let _ = self.send(niri_ipc::Request::Action(
+                    niri_ipc::Action::SetColumnWidth {
+                        change: niri_ipc::SizeChange::SetProportion(width * 100.0),
+                    },
+                ))?;
             }
         }
+        Ok(())
+    }
 
+    fn post_load(&mut self) -> Result<()> {
         // going back to the first workspace
         let first_workspace = niri_ipc::WorkspaceReferenceArg::Index(1);
         debug!("focusing first workspace: {:?}", first_workspace);
@@ -171,100 +512,91 @@ impl Niri {
         Ok(())
     }
 
-    /// Spawns a command and waits for it to finish.
-    /// TODO: wait is not implemented yet, so it just spawns the command.
-    fn spawn_and_wait(&mut self, node: &Node) -> Result<()> {
-        let app_id = node
-            .app_id
-            .as_deref()
-            .context("app_id is required to spawn an application")?;
-        let before_count = self.count_app_ids(app_id).context("on count_app_ids()")?;
-
-        let cmd = if let Some(desktop_file) = &node.desktop_entry {
-            debug!("\tspawning from desktop entry: {desktop_file}");
-            Some(format!(
-                "{} \"{}\"",
-                self.cfg.desktop_exec,
-                desktop_file.replace("\"", "\\\"")
-            ))
-        } else if let Some(exec) = &node.exec {
-            debug!("\tspawning from exec: {exec}");
-            Some(exec.replace("\"", "\\\""))
-        } else {
-            debug!("\tspawning from app_id: {:?}", app_id);
-            app_id.to_string().into()
-        };
-
-        let Some(cmd) = cmd else {
-            bail!("cannot spawn application without app_id, desktop_entry or exec");
-        };
-
-        let _ = self
-            .send(niri_ipc::Request::Action(niri_ipc::Action::Spawn {
-                command: vec!["sh".to_string(), "-c".to_string(), cmd.to_string()],
-            }))
-            .context(format!("on spawn action with command: {cmd}"))?;
-
-        if self.dry_run {
-            info!("dry run mode, not waiting for app to spawn: {app_id}");
-            return Ok(());
-        }
+    fn config(&self) -> &Config {
+        &self.cfg
+    }
 
-        let now = Instant::now();
-        while let Ok(after_count) = self.count_app_ids(app_id).context("on count_app_ids()") {
-            if after_count > before_count {
-                break;
-            }
-            if now.elapsed() > node.timeout.unwrap_or(MAX_WAIT_DURATION) {
-                warn!(
-                    "timeout reached while waiting for app with id {} to spawn, current count: {after_count}",
-                    app_id
-                );
-            }
-            info!(
-                "waiting 100ms for app with id {} to spawn, current count: {after_count}",
-                app_id
-            );
-            thread::sleep(Duration::from_millis(100));
-        }
-        Ok(())
+    fn spawner(&mut self) -> &mut Spawner {
+        &mut self.spawner
     }
 
-    fn count_app_ids(&mut self, app_id: &str) -> Result<usize> {
-        let mut count = 0;
-        let windows = self.fetch_windows().context("on fetch_windows()")?;
+    fn window_events(&mut self) -> Result<Box<dyn backend::WindowEvents>> {
+        let before = self
+            .fetch_windows()
+            .context("on fetch_windows()")?
+            .iter()
+            .map(|window| window.id)
+            .collect();
+        Ok(Box::new(WindowEvents::open(before)?))
+    }
 
-        for window in windows {
-            if let Some(id) = &window.app_id
-                && id == app_id
-            {
-                count += 1;
-            }
+    /// Brings the window `id` to the focused workspace, where a spawned window shows up,
+    /// and focuses it so the next one opens on its right.
+    fn place(&mut self, id: i64) -> Result<()> {
+        let workspaces = self.fetch_workspaces().context("on fetch_workspaces()")?;
+        if let Some(workspace) = workspaces.iter().find(|ws| ws.is_focused) {
+            let _ = self.send(niri_ipc::Request::Action(
+                niri_ipc::Action::MoveWindowToWorkspace {
+                    window_id: Some(id as u64),
+                    reference: niri_ipc::WorkspaceReferenceArg::Id(workspace.id),
+                    focus: false,
+                },
+            ))?;
         }
+        self.focus_window(id)
+    }
 
-        Ok(count)
+    /// The empty workspace ending the focused output.
+    fn focus_spawn_workspace(&mut self) -> Result<()> {
+        self.focus(niri_ipc::Action::FocusWorkspace {
+            reference: niri_ipc::WorkspaceReferenceArg::Index(u8::MAX),
+        })
     }
+}
 
-    fn fetch_windows(&mut self) -> Result<Vec<niri_ipc::Window>> {
-        let response = self
-            .send(niri_ipc::Request::Windows)
-            .context("on self.send(windows)")?;
+/// The id of a niri window, and what tells it apart, see `Node::matches`.
+fn identity(window: &niri_ipc::Window) -> Node {
+    Node {
+        id: Some(window.id as i64),
+        pid: window.pid,
+        app_id: window.app_id.clone(),
+        title: window.title.clone(),
+        ..Default::default()
+    }
+}
 
-        match response {
-            niri_ipc::Response::Windows(windows) => Ok(windows),
-            _ => bail!("unexpected response type from Niri, expected Windows"),
+/// Puts the columns of a saved workspace, with their width, into `columns`.
+///
+/// Vertical containers are columns, windows outside of one are alone in their column,
+/// so trees saved before columns were known load as one column per window.
+fn collect_columns<'a>(node: &'a Node, columns: &mut Vec<(Option<f64>, Vec<&'a Node>)>) {
+    for child in node.nodes.iter() {
+        if child.nodes.is_empty() {
+            if child.layout.is_none() {
+                columns.push((child.percent, vec![child]));
+            }
+        } else if matches!(
+            child.layout,
+            NodeLayout::SplitV | NodeLayout::Tabbed | NodeLayout::Stacked
+        ) {
+            let mut windows = vec![];
+            collect_windows(child, &mut windows);
+            columns.push((child.percent, windows));
+        } else {
+            collect_columns(child, columns);
         }
     }
+}
 
-    fn send(&mut self, request: niri_ipc::Request) -> Result<niri_ipc::Response> {
-        if self.dry_run {
-            info!("dry run mode, not sending request: {:?}", request);
-            return Ok(niri_ipc::Response::Handled);
+fn collect_windows<'a>(node: &'a Node, windows: &mut Vec<&'a Node>) {
+    for child in node.nodes.iter() {
+        if child.nodes.is_empty() {
+            if child.layout.is_none() {
+                windows.push(child);
+            }
+        } else {
+            collect_windows(child, windows);
         }
-        self.socket
-            .send(request)
-            .context("on socket.send()")?
-            .map_err(|e| anyhow!("on decoding Niri answer: {:?}", e))
     }
 }
 
@@ -275,11 +607,16 @@ impl Niri {
 /// not start at 1 — they keep growing as workspaces are created/destroyed during a
 /// session (common with named workspaces). So we map each window to its workspace by
 /// looking up its id, never by arithmetic on the id.
+/// `columns` are the window ids of each column, the windows missing from them get their
+/// own column.
 fn build_tree(
     mut workspaces: Vec<niri_ipc::Workspace>,
     windows: Vec<niri_ipc::Window>,
+    outputs: &[Output],
+    columns: &[Vec<u64>],
 ) -> Result<Vec<Node>> {
-    workspaces.sort_by_key(|w| w.idx);
+    // `idx` is the position on the output
+    workspaces.sort_by(|a, b| (&a.output, a.idx).cmp(&(&b.output, b.idx)));
 
     let mut nodes = Vec::with_capacity(workspaces.len());
     // workspace id -> index in `nodes`
@@ -287,20 +624,39 @@ fn build_tree(
     for (idx, workspace) in workspaces.into_iter().enumerate() {
         debug!("workspace: {workspace:?}");
         id_to_idx.insert(workspace.id, idx);
+        let output = workspace.output.map(|name| {
+            outputs
+                .iter()
+                .find(|output| output.name == name)
+                .cloned()
+                .unwrap_or(Output {
+                    name,
+                    ..Default::default()
+                })
+        });
         let node = Node {
             name: Some(workspace.name.unwrap_or(idx.to_string())),
+            output,
             node_type: NodeType::Workspace,
-            // niri does not provide layout, so we default to SplitH
-            nodes: vec![Node {
-                node_type: NodeType::Con,
-                layout: NodeLayout::SplitH,
-                ..Default::default()
-            }],
+            // columns, left to right
+            layout: NodeLayout::SplitH,
             ..Default::default()
         };
         nodes.push(node);
     }
 
+    // windows in column order, then the ones outside of any column
+    let mut windows = windows;
+    windows.sort_by_key(|window| {
+        columns
+            .iter()
+            .flatten()
+            .position(|&id| id == window.id)
+            .unwrap_or(usize::MAX)
+    });
+    // workspace index -> the index in `columns` of each of its columns
+    let mut workspace_columns = vec![vec![]; nodes.len()];
+
     for window in windows {
         debug!("window: {window:?}");
         let Some(workspace_id) = window.workspace_id else {
@@ -316,15 +672,39 @@ fn build_tree(
             continue;
         };
 
-        let node = Node {
+        let mut node = Node {
             node_type: NodeType::Con,
-            app_id: window.app_id,
-            ..Default::default()
+            ..identity(&window)
         };
 
-        // niri does not provide layout, so we default to the first found Con(tainer).
-        // `nodes[workspace_idx].nodes[0]` always exists: we pushed exactly one Con above.
-        nodes[workspace_idx].nodes[0].nodes.push(node);
+        if let Some(pid) = &window.pid {
+            node.exec = match extract_cmdline(pid) {
+                Ok(args) => Some(Exec::Argv(args)),
+                Err(e) => {
+                    warn!("failed to extract command line for PID {pid}: {e}");
+                    None
+                }
+            };
+            node.cwd = extract_cwd(pid).ok();
+        }
+
+        // windows are visited by column, the ones of a column come one after the other
+        let column = columns.iter().position(|c| c.contains(&window.id));
+        let workspace = &mut nodes[workspace_idx];
+        match column {
+            Some(column) if workspace_columns[workspace_idx].last() == Some(&column) => {
+                workspace.nodes.last_mut().unwrap().nodes.push(node);
+            }
+            _ => {
+                workspace_columns[workspace_idx].push(column.unwrap_or(usize::MAX));
+                workspace.nodes.push(Node {
+                    node_type: NodeType::Con,
+                    layout: NodeLayout::SplitV,
+                    nodes: vec![node],
+                    ..Default::default()
+                });
+            }
+        }
     }
 
     Ok(nodes)
@@ -332,7 +712,13 @@ fn build_tree(
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use std::{path::PathBuf, time::Duration};
+
+    use super::{
+        fake::{FakeNiri, Spawn},
+        *,
+    };
+    use crate::{backend, models::save_tree};
 
     fn ws(id: u64, idx: u8, name: Option<&str>) -> niri_ipc::Workspace {
         niri_ipc::Workspace {
@@ -378,7 +764,7 @@ mod tests {
             win(102, "discord", Some(9)),
         ];
 
-        let tree = build_tree(workspaces, windows).expect("build_tree should not fail");
+        let tree = build_tree(workspaces, windows, &[], &[]).expect("build_tree should not fail");
 
         // ordered by idx: term(idx 1), web(idx 2), chat(idx 3)
         assert_eq!(tree.len(), 3);
@@ -405,11 +791,621 @@ mod tests {
             win(101, "ghost", Some(42)), // workspace 42 no longer exists
         ];
 
-        let tree = build_tree(workspaces, windows).expect("build_tree should not fail");
+        let tree = build_tree(workspaces, windows, &[], &[]).expect("build_tree should not fail");
 
         assert_eq!(tree.len(), 1);
         let wins = &tree[0].nodes[0].nodes;
         assert_eq!(wins.len(), 1);
         assert_eq!(wins[0].app_id.as_deref(), Some("firefox"));
     }
+
+    #[test]
+    fn saves_the_command_line_of_windows() {
+        let workspaces = vec![ws(1, 1, Some("main"))];
+        let pid = std::process::id() as i32;
+        let windows = vec![
+            niri_ipc::Window {
+                pid: Some(pid),
+                ..win(100, "org.wezfurlong.wezterm", Some(1))
+            },
+            win(101, "firefox", Some(1)),
+        ];
+
+        let tree = build_tree(workspaces, windows, &[], &[]).expect("build_tree should not fail");
+
+        let wins: Vec<_> = tree[0]
+            .nodes
+            .iter()
+            .map(|column| &column.nodes[0])
+            .collect();
+        assert_eq!(
+            wins[0].exec,
+            Some(Exec::Argv(extract_cmdline(&pid).unwrap()))
+        );
+        assert_eq!(wins[0].cwd, Some(extract_cwd(&pid).unwrap()));
+        assert_eq!(wins[1].exec, None);
+    }
+
+    /// A backend launching the apps through the fake.
+    fn backend(niri: &FakeNiri, cfg: Config, dry_run: bool, no_kill: bool) -> Niri {
+        let mut backend = Niri::new(cfg, dry_run, no_kill).unwrap();
+        backend.spawner.launch = niri.launcher();
+        backend
+    }
+
+    fn spawn(app_id: &str) -> Spawn {
+        Spawn {
+            command: app_id.to_string(),
+            app_id: app_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn clear_closes_every_window() {
+        let niri = FakeNiri::start();
+        let term = niri.add_workspace(Some("term"), &["alacritty"]);
+        niri.add_workspace(Some("web"), &["firefox", "firefox"]);
+        niri.add_window(term, "foot");
+
+        Niri::new(Config::default(), false, false)
+            .unwrap()
+            .clear(None)
+            .unwrap();
+
+        assert!(niri.app_ids("term").is_empty());
+        assert!(niri.app_ids("web").is_empty());
+        let closed = niri
+            .actions()
+            .into_iter()
+            .filter(|a| matches!(a, niri_ipc::Action::CloseWindow { id: Some(_) }))
+            .count();
+        assert_eq!(closed, 4);
+    }
+
+    #[test]
+    fn clear_closes_only_the_given_workspace() {
+        let niri = FakeNiri::start();
+        niri.add_workspace(Some("term"), &["alacritty"]);
+        niri.add_workspace(Some("web"), &["firefox", "firefox"]);
+
+        Niri::new(Config::default(), false, false)
+            .unwrap()
+            .clear(Some("web"))
+            .unwrap();
+
+        assert_eq!(niri.app_ids("term"), ["alacritty"]);
+        assert!(niri.app_ids("web").is_empty());
+    }
+
+    /// Saves a tree with a "term" workspace running foot and a "web" one running firefox.
+    fn term_and_web_tree(niri: &FakeNiri) -> std::path::PathBuf {
+        let workspace = |name: &str, app_id: &str| Node {
+            name: Some(name.to_string()),
+            node_type: NodeType::Workspace,
+            nodes: vec![Node {
+                app_id: Some(app_id.to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let tree = vec![workspace("term", "foot"), workspace("web", "firefox")];
+        let tree_path = niri.dir().join("tree.yaml");
+        save_tree(&tree_path, &tree).unwrap();
+        tree_path
+    }
+
+    #[test]
+    fn load_single_workspace_leaves_others_alone() {
+        let niri = FakeNiri::start();
+        niri.add_workspace(Some("term"), &["alacritty"]);
+        niri.add_workspace(None, &["htop"]);
+        niri.add_workspace(Some("web"), &["chromium"]);
+        niri.on_spawn(spawn("firefox"));
+        let tree_path = term_and_web_tree(&niri);
+
+        let mut n = backend(&niri, Config::default(), false, false);
+        backend::load(&mut n, &tree_path, Some("web"), false, false).unwrap();
+
+        assert_eq!(niri.app_ids("term"), ["alacritty"]);
+        assert_eq!(niri.app_ids("web"), ["firefox"]);
+        let names: Vec<_> = niri.workspaces().into_iter().map(|ws| ws.name).collect();
+        assert_eq!(
+            names[..3],
+            [Some("term".to_string()), None, Some("web".to_string())]
+        );
+    }
+
+    #[test]
+    fn load_single_missing_workspace_names_an_empty_one() {
+        let niri = FakeNiri::start();
+        niri.add_workspace(Some("term"), &["alacritty"]);
+        niri.add_workspace(None, &["htop"]);
+        niri.on_spawn(spawn("firefox"));
+        let tree_path = term_and_web_tree(&niri);
+
+        let mut n = backend(&niri, Config::default(), false, false);
+        backend::load(&mut n, &tree_path, Some("web"), false, false).unwrap();
+
+        assert_eq!(niri.app_ids("term"), ["alacritty"]);
+        assert_eq!(niri.app_ids("web"), ["firefox"]);
+        let names: Vec<_> = niri.workspaces().into_iter().map(|ws| ws.name).collect();
+        assert_eq!(
+            names[..3],
+            [Some("term".to_string()), None, Some("web".to_string())]
+        );
+    }
+
+    #[test]
+    fn save_then_load_round_trip() {
+        let tree = {
+            let niri = FakeNiri::start();
+            niri.add_workspace(Some("term"), &["alacritty"]);
+            niri.add_workspace(Some("web"), &["firefox", "firefox"]);
+
+            Niri::new(Config::default(), false, false)
+                .unwrap()
+                .get_tree()
+                .unwrap()
+        };
+
+        let niri = FakeNiri::start();
+        niri.on_spawn(spawn("alacritty"));
+        niri.on_spawn(spawn("firefox"));
+        let tree_path = niri.dir().join("tree.yaml");
+        save_tree(&tree_path, &tree).unwrap();
+
+        let mut n = backend(&niri, Config::default(), false, false);
+        backend::load(&mut n, &tree_path, None, false, false).unwrap();
+
+        assert_eq!(niri.app_ids("term"), ["alacritty"]);
+        assert_eq!(niri.app_ids("web"), ["firefox", "firefox"]);
+        let spawned = niri.launched();
+        assert_eq!(spawned, ["alacritty", "firefox", "firefox"]);
+        // back on the first workspace once loaded
+        assert!(niri.workspaces()[0].is_focused);
+    }
+
+    #[test]
+    fn save_keeps_the_output_of_each_workspace() {
+        let niri = FakeNiri::start();
+        niri.add_output("HDMI-A-1");
+        niri.add_workspace(Some("term"), &["foot"]);
+        niri.add_workspace_on("HDMI-A-1", Some("web"), &["firefox"]);
+
+        let tree = Niri::new(Config::default(), false, false)
+            .unwrap()
+            .get_tree()
+            .unwrap();
+
+        let output = |name: &str| {
+            tree.iter()
+                .find(|ws| ws.name.as_deref() == Some(name))
+                .and_then(|ws| ws.output.clone())
+                .unwrap()
+        };
+        assert_eq!(output("term").name, "eDP-1");
+        let hdmi = output("web");
+        assert_eq!(hdmi.name, "HDMI-A-1");
+        assert_eq!(hdmi.serial.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn load_puts_workspaces_back_on_their_monitor() {
+        let tree = {
+            let niri = FakeNiri::start();
+            niri.add_output("HDMI-A-1");
+            niri.add_workspace(Some("term"), &["foot"]);
+            niri.add_workspace_on("HDMI-A-1", Some("web"), &["firefox"]);
+            niri.add_workspace_on("HDMI-A-1", Some("chat"), &["slack"]);
+
+            Niri::new(Config::default(), false, false)
+                .unwrap()
+                .get_tree()
+                .unwrap()
+        };
+
+        // the external screen is now plugged into another port
+        let niri = FakeNiri::start();
+        niri.add_output("DP-2");
+        for app_id in ["foot", "firefox", "slack"] {
+            niri.on_spawn(spawn(app_id));
+        }
+        let tree_path = niri.dir().join("tree.yaml");
+        save_tree(&tree_path, &tree).unwrap();
+
+        let mut n = backend(&niri, Config::default(), false, false);
+        backend::load(&mut n, &tree_path, None, false, false).unwrap();
+
+        assert_eq!(niri.output_of("term").as_deref(), Some("eDP-1"));
+        assert_eq!(niri.output_of("web").as_deref(), Some("DP-2"));
+        assert_eq!(niri.output_of("chat").as_deref(), Some("DP-2"));
+        assert_eq!(niri.app_ids("web"), ["firefox"]);
+        assert_eq!(niri.app_ids("chat"), ["slack"]);
+        let on_dp2: Vec<_> = niri
+            .workspaces()
+            .into_iter()
+            .filter(|ws| ws.output.as_deref() == Some("DP-2"))
+            .filter_map(|ws| ws.name)
+            .collect();
+        assert_eq!(on_dp2[..2], ["web", "chat"]);
+    }
+
+    #[test]
+    fn save_walks_the_columns() {
+        let niri = FakeNiri::start();
+        let term = niri.add_workspace(Some("term"), &["foot"]);
+        let htop = niri.add_window(term, "htop");
+        niri.add_window(term, "firefox");
+        niri.consume_left(htop);
+        let focused = niri.workspaces().into_iter().find(|ws| ws.is_focused);
+
+        let tree = Niri::new(Config::default(), false, false)
+            .unwrap()
+            .get_tree()
+            .unwrap();
+
+        let term = tree
+            .iter()
+            .find(|ws| ws.name.as_deref() == Some("term"))
+            .unwrap();
+        let columns: Vec<Vec<_>> = term
+            .nodes
+            .iter()
+            .map(|column| {
+                assert_eq!(column.layout, NodeLayout::SplitV);
+                column
+                    .nodes
+                    .iter()
+                    .map(|w| w.app_id.clone().unwrap())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(columns, [vec!["foot", "htop"], vec!["firefox"]]);
+        // niri does not tell the width of a column
+        assert!(term.nodes.iter().all(|column| column.percent.is_none()));
+        // the focus is back where it was
+        assert_eq!(
+            niri.workspaces().into_iter().find(|ws| ws.is_focused),
+            focused
+        );
+    }
+
+    #[test]
+    fn load_rebuilds_columns_and_their_width() {
+        let niri = FakeNiri::start();
+        for app_id in ["foot", "htop", "firefox"] {
+            niri.on_spawn(spawn(app_id));
+        }
+        let window = |app_id: &str| Node {
+            app_id: Some(app_id.to_string()),
+            ..Default::default()
+        };
+        let column = |percent, nodes| Node {
+            node_type: NodeType::Con,
+            layout: NodeLayout::SplitV,
+            percent,
+            nodes,
+            ..Default::default()
+        };
+        let tree = vec![Node {
+            name: Some("term".to_string()),
+            node_type: NodeType::Workspace,
+            layout: NodeLayout::SplitH,
+            nodes: vec![
+                column(Some(0.6), vec![window("foot"), window("htop")]),
+                column(None, vec![window("firefox")]),
+            ],
+            ..Default::default()
+        }];
+        let tree_path = niri.dir().join("tree.yaml");
+        save_tree(&tree_path, &tree).unwrap();
+
+        let mut n = backend(&niri, Config::default(), false, false);
+        backend::load(&mut n, &tree_path, None, false, false).unwrap();
+
+        assert_eq!(
+            niri.columns("term"),
+            [vec!["foot", "htop"], vec!["firefox"]]
+        );
+        assert_eq!(niri.widths("term"), [Some(0.6), None]);
+    }
+
+    #[test]
+    fn load_starts_a_column_with_its_first_window_to_show_up() {
+        let niri = FakeNiri::start();
+        niri.on_spawn(spawn("foot"));
+        niri.on_spawn(spawn("btop"));
+        let column = |nodes| Node {
+            node_type: NodeType::Con,
+            layout: NodeLayout::SplitV,
+            nodes,
+            ..Default::default()
+        };
+
+        let (result, spawned) = load_term(
+            &niri,
+            Config::default(),
+            vec![
+                column(vec![app("foot", 1, 200)]),
+                column(vec![app("never", 1, 200), app("btop", 1, 200)]),
+            ],
+        );
+
+        result.unwrap();
+        assert_eq!(spawned, ["foot", "never", "btop"]);
+        assert_eq!(niri.columns("term"), [vec!["foot"], vec!["btop"]]);
+    }
+
+    #[test]
+    fn load_replaces_existing_windows() {
+        let niri = FakeNiri::start();
+        niri.add_workspace(Some("term"), &["old"]);
+        niri.on_spawn(spawn("foot"));
+        let tree_path = niri.dir().join("tree.yaml");
+        let tree = vec![Node {
+            name: Some("term".to_string()),
+            node_type: NodeType::Workspace,
+            nodes: vec![Node {
+                node_type: NodeType::Con,
+                layout: NodeLayout::SplitH,
+                nodes: vec![Node {
+                    app_id: Some("foot".to_string()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }];
+        save_tree(&tree_path, &tree).unwrap();
+
+        let mut n = backend(&niri, Config::default(), false, false);
+        backend::load(&mut n, &tree_path, None, false, false).unwrap();
+
+        assert_eq!(niri.app_ids("term"), ["foot"]);
+    }
+
+    #[test]
+    fn reconcile_spawns_only_missing_windows() {
+        let niri = FakeNiri::start();
+        let term = niri.add_workspace(Some("term"), &["alacritty", "htop"]);
+        niri.on_spawn(spawn("foot"));
+        let tree_path = niri.dir().join("tree.yaml");
+        let tree = vec![Node {
+            name: Some("term".to_string()),
+            node_type: NodeType::Workspace,
+            nodes: vec![Node {
+                node_type: NodeType::Con,
+                layout: NodeLayout::SplitH,
+                nodes: ["alacritty", "foot"]
+                    .into_iter()
+                    .map(|app_id| Node {
+                        app_id: Some(app_id.to_string()),
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }];
+        save_tree(&tree_path, &tree).unwrap();
+
+        let mut n = backend(&niri, Config::default(), false, false);
+        backend::load(&mut n, &tree_path, None, true, false).unwrap();
+
+        assert_eq!(niri.app_ids("term"), ["alacritty", "foot"]);
+        let workspaces = niri.workspaces();
+        assert!(workspaces.iter().any(|ws| ws.id == term));
+        let spawned = niri.launched().len();
+        assert_eq!(spawned, 1);
+    }
+
+    #[test]
+    fn clear_with_no_kill_closes_nothing() {
+        let niri = FakeNiri::start();
+        niri.add_workspace(Some("term"), &["alacritty"]);
+
+        Niri::new(Config::default(), false, true)
+            .unwrap()
+            .clear(None)
+            .unwrap();
+
+        assert_eq!(niri.app_ids("term"), ["alacritty"]);
+        assert!(
+            !niri
+                .actions()
+                .iter()
+                .any(|a| matches!(a, niri_ipc::Action::CloseWindow { .. }))
+        );
+    }
+
+    #[test]
+    fn load_with_no_kill_adopts_existing_windows() {
+        let niri = FakeNiri::start();
+        niri.add_workspace(Some("term"), &["foot", "htop"]);
+        niri.on_spawn(spawn("firefox"));
+        let tree_path = term_and_web_tree(&niri);
+
+        // what main does with `--no-kill` and the default `existing_windows: adopt`
+        let mut n = backend(&niri, Config::default(), false, true);
+        backend::load(&mut n, &tree_path, None, true, false).unwrap();
+
+        assert_eq!(niri.app_ids("term"), ["foot", "htop"]);
+        assert_eq!(niri.app_ids("web"), ["firefox"]);
+        let spawned = niri.launched().len();
+        assert_eq!(spawned, 1);
+    }
+
+    #[test]
+    fn load_never_renames_config_declared_workspaces() {
+        let niri = FakeNiri::start();
+        // declared in the niri config, it exists even when empty
+        niri.add_workspace(Some("chat"), &[]);
+        niri.add_workspace(Some("term"), &["alacritty"]);
+        niri.on_spawn(spawn("foot"));
+        niri.on_spawn(spawn("firefox"));
+        let tree_path = term_and_web_tree(&niri);
+
+        let mut n = backend(&niri, Config::default(), false, false);
+        backend::load(&mut n, &tree_path, None, false, false).unwrap();
+
+        assert!(niri.app_ids("chat").is_empty());
+        assert_eq!(niri.app_ids("term"), ["foot"]);
+        assert_eq!(niri.app_ids("web"), ["firefox"]);
+        let names: Vec<_> = niri
+            .workspaces()
+            .into_iter()
+            .filter_map(|ws| ws.name)
+            .collect();
+        assert_eq!(names, ["chat", "term", "web"]);
+        // only the new workspace is named, by its id
+        let renames: Vec<_> = niri
+            .actions()
+            .into_iter()
+            .filter_map(|a| match a {
+                niri_ipc::Action::SetWorkspaceName { name, workspace } => Some((name, workspace)),
+                _ => None,
+            })
+            .collect();
+        assert!(matches!(
+            renames.as_slice(),
+            [(name, Some(niri_ipc::WorkspaceReferenceArg::Id(_)))] if name == "web"
+        ));
+    }
+
+    /// Loads a "term" workspace with `windows` and returns the spawned commands.
+    fn load_term(niri: &FakeNiri, cfg: Config, windows: Vec<Node>) -> (Result<()>, Vec<String>) {
+        let opts = Opts {
+            cfg,
+            ..Default::default()
+        };
+        load_term_with(niri, windows, opts)
+    }
+
+    /// How `load_term_with` loads a tree.
+    #[derive(Default)]
+    struct Opts {
+        cfg: Config,
+        parallel: bool,
+    }
+
+    fn load_term_with(
+        niri: &FakeNiri,
+        windows: Vec<Node>,
+        opts: Opts,
+    ) -> (Result<()>, Vec<String>) {
+        let tree = vec![Node {
+            name: Some("term".to_string()),
+            node_type: NodeType::Workspace,
+            nodes: windows,
+            ..Default::default()
+        }];
+        let tree_path = niri.dir().join("tree.yaml");
+        save_tree(&tree_path, &tree).unwrap();
+
+        let mut n = backend(niri, opts.cfg, false, false);
+        let result = backend::load(&mut n, &tree_path, None, false, opts.parallel);
+        let spawned = niri.launched();
+        (result, spawned)
+    }
+
+    fn app(app_id: &str, retry: u8, timeout_ms: u64) -> Node {
+        Node {
+            app_id: Some(app_id.to_string()),
+            retry: Some(retry),
+            timeout: Some(Duration::from_millis(timeout_ms)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn load_starts_apps_in_their_directory() {
+        let niri = FakeNiri::start();
+        niri.on_spawn(Spawn {
+            cwd: Some(PathBuf::from("/src/api")),
+            ..spawn("foot")
+        });
+
+        let terminal = Node {
+            cwd: Some(PathBuf::from("/src/api")),
+            ..app("foot", 1, 200)
+        };
+        let (result, _) = load_term(&niri, Config::default(), vec![terminal]);
+
+        result.unwrap();
+        assert_eq!(niri.app_ids("term"), ["foot"]);
+    }
+
+    #[test]
+    fn load_waits_for_window_events_instead_of_polling() {
+        let niri = FakeNiri::start();
+        niri.on_spawn(Spawn {
+            delay: Duration::from_millis(300),
+            ..spawn("slow")
+        });
+
+        let (result, _) = load_term(&niri, Config::default(), vec![app("slow", 1, 5000)]);
+
+        result.unwrap();
+        assert_eq!(niri.app_ids("term"), ["slow"]);
+        let streams = niri
+            .requests()
+            .into_iter()
+            .filter(|r| matches!(r, niri_ipc::Request::EventStream))
+            .count();
+        assert_eq!(streams, 1);
+        // once to clear, once to know the windows already open before spawning
+        let windows = niri
+            .requests()
+            .into_iter()
+            .filter(|r| matches!(r, niri_ipc::Request::Windows))
+            .count();
+        assert_eq!(windows, 2);
+    }
+
+    #[test]
+    fn load_in_parallel_builds_the_same_columns() {
+        let niri = FakeNiri::start();
+        for app_id in ["foot", "htop", "firefox"] {
+            niri.on_spawn(Spawn {
+                delay: Duration::from_millis(400),
+                ..spawn(app_id)
+            });
+        }
+        let column = Node {
+            node_type: NodeType::Con,
+            layout: NodeLayout::SplitV,
+            nodes: vec![app("foot", 1, 5000), app("htop", 1, 5000)],
+            ..Default::default()
+        };
+
+        let opts = Opts {
+            parallel: true,
+            ..Default::default()
+        };
+        let (result, spawned) = load_term_with(&niri, vec![column, app("firefox", 1, 5000)], opts);
+
+        result.unwrap();
+        // all launched on the empty last workspace before the first one is placed
+        let focused: Vec<_> = niri
+            .actions()
+            .into_iter()
+            .filter_map(|a| match a {
+                niri_ipc::Action::FocusWorkspace { reference } => Some(reference),
+                _ => None,
+            })
+            .collect();
+        assert!(matches!(
+            focused[..2],
+            [
+                niri_ipc::WorkspaceReferenceArg::Index(u8::MAX),
+                niri_ipc::WorkspaceReferenceArg::Id(_)
+            ]
+        ));
+        assert_eq!(spawned, ["foot", "htop", "firefox"]);
+        assert_eq!(
+            niri.columns("term"),
+            [vec!["foot", "htop"], vec!["firefox"]]
+        );
+    }
 }
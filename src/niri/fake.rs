@@ -0,0 +1,850 @@
+//! An in-process stand-in for niri, answering `niri_ipc::Request`s on a temporary
+//! unix socket, so `Niri` can be tested on a machine without a compositor.
+//!
+//! It keeps a small window/workspace model reacting to the actions we send,
+//! including niri's dynamic workspaces: there is always one empty workspace at
+//! the end of each output, and unnamed empty workspaces go away once left.
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use niri_ipc::{
+    Action, Event, LogicalOutput, Output, Reply, Request, Response, SizeChange, Transform, Window,
+    Workspace, WorkspaceReferenceArg,
+};
+
+use crate::{backend::Launch, util::ENV_LOCK};
+
+/// A window to open when `command` is launched.
+#[derive(Clone, Debug, Default)]
+pub struct Spawn {
+    /// The launched command, as a shell reads it: the script, or the quoted arguments.
+    pub command: String,
+    /// When given, only a launch in this directory opens the window.
+    pub cwd: Option<PathBuf>,
+    pub app_id: String,
+    pub title: Option<String>,
+    /// How long the app takes to show its window.
+    pub delay: Duration,
+    /// How many launches are ignored before the window shows up, to simulate a flaky app.
+    pub skip: u32,
+}
+
+/// The made up pid of the first launched app, the next ones follow.
+const FIRST_PID: i32 = 1 << 23;
+
+struct State {
+    outputs: Vec<Output>,
+    workspaces: Vec<Workspace>,
+    /// Windows in layout order, left to right then top to bottom, per workspace.
+    windows: Vec<Window>,
+    /// Window id -> id of its column, the windows of a column are next to each other.
+    columns: HashMap<u64, u64>,
+    /// Column id -> width, as a proportion of the output.
+    widths: HashMap<u64, f64>,
+    focused_workspace: u64,
+    next_id: u64,
+    requests: Vec<Request>,
+    spawns: Vec<Spawn>,
+    /// The launched commands, in order.
+    launched: Vec<String>,
+    /// Connections that asked for the event stream.
+    subscribers: Vec<UnixStream>,
+}
+
+impl State {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn focused_output(&self) -> Option<String> {
+        self.workspace(self.focused_workspace)
+            .and_then(|ws| ws.output.clone())
+    }
+
+    fn workspace(&self, id: u64) -> Option<&Workspace> {
+        self.workspaces.iter().find(|ws| ws.id == id)
+    }
+
+    fn workspace_mut(&mut self, id: u64) -> Option<&mut Workspace> {
+        self.workspaces.iter_mut().find(|ws| ws.id == id)
+    }
+
+    fn resolve(&self, reference: &WorkspaceReferenceArg) -> Option<u64> {
+        match reference {
+            WorkspaceReferenceArg::Id(id) => self.workspace(*id).map(|ws| ws.id),
+            WorkspaceReferenceArg::Name(name) => self
+                .workspaces
+                .iter()
+                .find(|ws| ws.name.as_deref() == Some(name))
+                .map(|ws| ws.id),
+            WorkspaceReferenceArg::Index(idx) => {
+                let output = self.focused_output();
+                let on_output = self.workspaces.iter().filter(|ws| ws.output == output);
+                // like niri, an index past the end is the last (empty) workspace
+                on_output
+                    .clone()
+                    .find(|ws| ws.idx == *idx)
+                    .or_else(|| on_output.max_by_key(|ws| ws.idx))
+                    .map(|ws| ws.id)
+            }
+        }
+    }
+
+    fn add_workspace(&mut self, output: &str, name: Option<&str>) -> u64 {
+        let id = self.next_id();
+        // new workspaces go before the trailing empty one
+        let idx = self
+            .workspaces
+            .iter()
+            .filter(|ws| ws.output.as_deref() == Some(output))
+            .count() as u8;
+        for ws in self.workspaces.iter_mut() {
+            if ws.output.as_deref() == Some(output) && ws.idx >= idx.max(1) {
+                ws.idx += 1;
+            }
+        }
+        self.workspaces.push(Workspace {
+            id,
+            idx: idx.max(1),
+            name: name.map(str::to_string),
+            output: Some(output.to_string()),
+            is_urgent: false,
+            is_active: false,
+            is_focused: false,
+            active_window_id: None,
+        });
+        id
+    }
+
+    fn focus_workspace(&mut self, id: u64) {
+        self.focused_workspace = id;
+        let active_window = self.workspace(id).and_then(|ws| ws.active_window_id);
+        for window in self.windows.iter_mut() {
+            window.is_focused = Some(window.id) == active_window;
+        }
+        self.normalize();
+    }
+
+    fn column_of(&self, window: u64) -> u64 {
+        self.columns[&window]
+    }
+
+    /// The columns of a workspace, left to right.
+    fn workspace_columns(&self, workspace_id: u64) -> Vec<u64> {
+        let mut columns: Vec<u64> = vec![];
+        for window in self
+            .windows
+            .iter()
+            .filter(|w| w.workspace_id == Some(workspace_id))
+        {
+            let column = self.column_of(window.id);
+            if columns.last() != Some(&column) {
+                columns.push(column);
+            }
+        }
+        columns
+    }
+
+    /// The windows of a column, top to bottom.
+    fn column_windows(&self, column: u64) -> Vec<u64> {
+        self.windows
+            .iter()
+            .map(|w| w.id)
+            .filter(|&id| self.column_of(id) == column)
+            .collect()
+    }
+
+    fn active_window(&self) -> Option<u64> {
+        self.workspace(self.focused_workspace)
+            .and_then(|ws| ws.active_window_id)
+    }
+
+    fn focus_window(&mut self, id: u64) {
+        if let Some(workspace_id) = self
+            .windows
+            .iter()
+            .find(|w| w.id == id)
+            .and_then(|w| w.workspace_id)
+        {
+            self.workspace_mut(workspace_id).unwrap().active_window_id = Some(id);
+            self.focus_workspace(workspace_id);
+        }
+    }
+
+    /// Moves `window` right after `after`, in the windows order.
+    fn move_after(&mut self, window: u64, after: Option<u64>) {
+        let from = self.windows.iter().position(|w| w.id == window).unwrap();
+        let moved = self.windows.remove(from);
+        let to = match after {
+            Some(after) => self.windows.iter().position(|w| w.id == after).unwrap() + 1,
+            None => self
+                .windows
+                .iter()
+                .position(|w| w.workspace_id == moved.workspace_id)
+                .unwrap_or(self.windows.len()),
+        };
+        self.windows.insert(to, moved);
+    }
+
+    /// Opens a window in a new column right of the active one of the focused workspace,
+    /// and focuses it.
+    fn open_window(&mut self, app_id: &str, title: Option<String>, pid: Option<i32>) -> u64 {
+        let id = self.next_id();
+        self.insert_window(Window {
+            id,
+            title,
+            app_id: Some(app_id.to_string()),
+            pid,
+            workspace_id: Some(self.focused_workspace),
+            is_focused: true,
+            is_floating: false,
+            is_urgent: false,
+        });
+        let window = self.windows.iter().find(|w| w.id == id).unwrap().clone();
+        self.emit(&Event::WindowOpenedOrChanged { window });
+        id
+    }
+
+    /// Puts `window` in a new column right of the active one of its workspace,
+    /// as the active window of the workspace.
+    fn insert_window(&mut self, window: Window) {
+        let workspace_id = window.workspace_id.expect("not a floating window");
+        let active = self
+            .workspace(workspace_id)
+            .and_then(|ws| ws.active_window_id);
+        let position = match active {
+            Some(active) => {
+                let column = self.column_of(active);
+                self.windows
+                    .iter()
+                    .rposition(|w| self.columns.get(&w.id) == Some(&column))
+                    .unwrap()
+                    + 1
+            }
+            None => self.windows.len(),
+        };
+        let column = self.next_id();
+        self.columns.insert(window.id, column);
+        if window.is_focused {
+            for window in self.windows.iter_mut() {
+                window.is_focused = false;
+            }
+        }
+        let id = window.id;
+        self.windows.insert(position, window);
+        self.workspace_mut(workspace_id).unwrap().active_window_id = Some(id);
+        self.normalize();
+    }
+
+    /// Moves a window to a new column right of the active one of another workspace.
+    fn move_window(&mut self, id: u64, workspace_id: u64, focus: bool) {
+        let Some(window) = self.windows.iter().find(|w| w.id == id).cloned() else {
+            return;
+        };
+        if window.workspace_id == Some(workspace_id) {
+            return;
+        }
+        let follow = focus && window.is_focused;
+        self.close_window(id);
+        self.insert_window(Window {
+            workspace_id: Some(workspace_id),
+            is_focused: false,
+            ..window
+        });
+        if follow {
+            self.focus_window(id);
+        }
+    }
+
+    /// Sends `event` to the event streams, forgetting the closed ones.
+    fn emit(&mut self, event: &Event) {
+        let mut line = serde_json::to_string(event).expect("serialize event");
+        line.push('\n');
+        self.subscribers
+            .retain_mut(|stream| stream.write_all(line.as_bytes()).is_ok());
+    }
+
+    fn close_window(&mut self, id: u64) {
+        let Some(position) = self.windows.iter().position(|w| w.id == id) else {
+            return;
+        };
+        let window = self.windows.remove(position);
+        if let Some(workspace_id) = window.workspace_id
+            && self.workspace(workspace_id).unwrap().active_window_id == Some(id)
+        {
+            let next = self
+                .windows
+                .iter()
+                .rfind(|w| w.workspace_id == Some(workspace_id))
+                .map(|w| w.id);
+            self.workspace_mut(workspace_id).unwrap().active_window_id = next;
+            if window.is_focused
+                && let Some(next) = next
+            {
+                self.windows
+                    .iter_mut()
+                    .filter(|w| w.id == next)
+                    .for_each(|w| w.is_focused = true);
+            }
+        }
+        self.normalize();
+    }
+
+    /// Applies niri's dynamic workspaces rules, and refreshes the indexes and flags.
+    fn normalize(&mut self) {
+        for output in self
+            .outputs
+            .iter()
+            .map(|o| o.name.clone())
+            .collect::<Vec<_>>()
+        {
+            let mut on_output: Vec<Workspace> = self
+                .workspaces
+                .iter()
+                .filter(|ws| ws.output.as_deref() == Some(&output))
+                .cloned()
+                .collect();
+            on_output.sort_by_key(|ws| ws.idx);
+            let is_empty =
+                |ws: &Workspace| !self.windows.iter().any(|w| w.workspace_id == Some(ws.id));
+            let last = on_output.len().saturating_sub(1);
+            let mut kept: Vec<Workspace> = on_output
+                .iter()
+                .enumerate()
+                .filter(|(i, ws)| {
+                    *i == last
+                        || ws.name.is_some()
+                        || ws.id == self.focused_workspace
+                        || !is_empty(ws)
+                })
+                .map(|(_, ws)| ws.clone())
+                .collect();
+            if kept
+                .last()
+                .is_none_or(|ws| ws.name.is_some() || !is_empty(ws))
+            {
+                self.next_id += 1;
+                kept.push(Workspace {
+                    id: self.next_id,
+                    idx: 0,
+                    name: None,
+                    output: Some(output.clone()),
+                    is_urgent: false,
+                    is_active: false,
+                    is_focused: false,
+                    active_window_id: None,
+                });
+            }
+            for (i, ws) in kept.iter_mut().enumerate() {
+                ws.idx = (i + 1) as u8;
+            }
+            self.workspaces
+                .retain(|ws| ws.output.as_deref() != Some(&output));
+            self.workspaces.extend(kept);
+        }
+        let focused = self.focused_workspace;
+        let focused_output = self.focused_output();
+        for ws in self.workspaces.iter_mut() {
+            ws.is_focused = ws.id == focused;
+            ws.is_active = ws.id == focused || (ws.output != focused_output && ws.idx == 1);
+        }
+    }
+
+    fn action(&mut self, action: Action) -> Reply {
+        match action {
+            Action::CloseWindow { id } => {
+                let id = id.or_else(|| self.windows.iter().find(|w| w.is_focused).map(|w| w.id));
+                if let Some(id) = id {
+                    self.close_window(id);
+                }
+            }
+            Action::FocusWindow { id } => self.focus_window(id),
+            Action::MoveWindowToWorkspace {
+                window_id,
+                reference,
+                focus,
+            } => {
+                let id =
+                    window_id.or_else(|| self.windows.iter().find(|w| w.is_focused).map(|w| w.id));
+                if let (Some(id), Some(workspace_id)) = (id, self.resolve(&reference)) {
+                    self.move_window(id, workspace_id, focus);
+                }
+            }
+            Action::FocusColumnFirst {} => {
+                let columns = self.workspace_columns(self.focused_workspace);
+                if let Some(&column) = columns.first() {
+                    self.focus_window(self.column_windows(column)[0]);
+                }
+            }
+            Action::FocusColumnRight {} => {
+                if let Some(active) = self.active_window() {
+                    let columns = self.workspace_columns(self.focused_workspace);
+                    let current = columns.iter().position(|&c| c == self.column_of(active));
+                    if let Some(&next) = current.and_then(|i| columns.get(i + 1)) {
+                        self.focus_window(self.column_windows(next)[0]);
+                    }
+                }
+            }
+            Action::FocusWindowTop {} => {
+                if let Some(active) = self.active_window() {
+                    self.focus_window(self.column_windows(self.column_of(active))[0]);
+                }
+            }
+            Action::FocusWindowDown {} => {
+                if let Some(active) = self.active_window() {
+                    let windows = self.column_windows(self.column_of(active));
+                    let current = windows.iter().position(|&w| w == active).unwrap();
+                    if let Some(&below) = windows.get(current + 1) {
+                        self.focus_window(below);
+                    }
+                }
+            }
+            Action::ConsumeOrExpelWindowLeft { id } => {
+                if let Some(id) = id.or(self.active_window()) {
+                    self.consume_or_expel_left(id);
+                }
+            }
+            Action::SetColumnWidth {
+                change: SizeChange::SetProportion(proportion),
+            } => {
+                if let Some(active) = self.active_window() {
+                    let column = self.column_of(active);
+                    self.widths.insert(column, proportion / 100.0);
+                }
+            }
+            Action::FocusWorkspace { reference } => {
+                if let Some(id) = self.resolve(&reference) {
+                    self.focus_workspace(id);
+                }
+            }
+            Action::FocusMonitor { output } => {
+                // like `is_active` below, the first workspace is the active one
+                if let Some(id) = self
+                    .workspaces
+                    .iter()
+                    .find(|ws| ws.output.as_deref() == Some(&output) && ws.idx == 1)
+                    .map(|ws| ws.id)
+                {
+                    self.focus_workspace(id);
+                }
+            }
+            Action::MoveWorkspaceToMonitor { output, reference } => {
+                let id = match reference {
+                    Some(reference) => self.resolve(&reference),
+                    None => Some(self.focused_workspace),
+                };
+                if let Some(id) = id
+                    && self.outputs.iter().any(|o| o.name == output)
+                {
+                    let workspace = self.workspace_mut(id).unwrap();
+                    if workspace.output.as_deref() != Some(&output) {
+                        // last, the trailing empty workspace is added back by `normalize`
+                        workspace.output = Some(output);
+                        workspace.idx = u8::MAX;
+                        self.normalize();
+                    }
+                }
+            }
+            Action::SetWorkspaceName { name, workspace } => {
+                let id = match workspace {
+                    Some(reference) => self.resolve(&reference),
+                    None => Some(self.focused_workspace),
+                };
+                if let Some(id) = id {
+                    self.workspace_mut(id).unwrap().name = Some(name);
+                    self.normalize();
+                }
+            }
+            _ => {}
+        }
+        Ok(Response::Handled)
+    }
+
+    /// Puts a window alone in its column at the bottom of the column on its left,
+    /// or takes it out of its column, into a new one on the left.
+    fn consume_or_expel_left(&mut self, id: u64) {
+        let Some(workspace_id) = self
+            .windows
+            .iter()
+            .find(|w| w.id == id)
+            .and_then(|w| w.workspace_id)
+        else {
+            return;
+        };
+        let column = self.column_of(id);
+        let columns = self.workspace_columns(workspace_id);
+        let current = columns.iter().position(|&c| c == column).unwrap();
+        if self.column_windows(column).len() == 1 {
+            let Some(&left) = current.checked_sub(1).and_then(|i| columns.get(i)) else {
+                return;
+            };
+            let bottom = *self.column_windows(left).last().unwrap();
+            self.move_after(id, Some(bottom));
+            self.columns.insert(id, left);
+        } else {
+            let before = current
+                .checked_sub(1)
+                .map(|i| *self.column_windows(columns[i]).last().unwrap());
+            self.move_after(id, before);
+            let new_column = self.next_id();
+            self.columns.insert(id, new_column);
+        }
+    }
+
+    /// Records the launch of `command`, opening the window of its app if one is expected,
+    /// and returns the made up pid of the launched process.
+    fn launch(&mut self, state: &Arc<Mutex<State>>, command: &str, cwd: Option<&Path>) -> i32 {
+        self.launched.push(command.to_string());
+        let pid = FIRST_PID + self.launched.len() as i32;
+        let Some(spawn) = self
+            .spawns
+            .iter_mut()
+            .find(|s| s.command == command && (s.cwd.is_none() || s.cwd.as_deref() == cwd))
+        else {
+            return pid;
+        };
+        if spawn.skip > 0 {
+            spawn.skip -= 1;
+            return pid;
+        }
+        let spawn = spawn.clone();
+        if spawn.delay.is_zero() {
+            self.open_window(&spawn.app_id, spawn.title, Some(pid));
+            return pid;
+        }
+        let state = Arc::clone(state);
+        thread::spawn(move || {
+            thread::sleep(spawn.delay);
+            lock(&state).open_window(&spawn.app_id, spawn.title, Some(pid));
+        });
+        pid
+    }
+
+    fn handle(&mut self, request: Request) -> Reply {
+        self.requests.push(request.clone());
+        match request {
+            Request::Workspaces => Ok(Response::Workspaces(self.workspaces.clone())),
+            Request::Windows => Ok(Response::Windows(self.windows.clone())),
+            Request::Outputs => Ok(Response::Outputs(
+                self.outputs
+                    .iter()
+                    .map(|o| (o.name.clone(), o.clone()))
+                    .collect(),
+            )),
+            Request::FocusedWindow => Ok(Response::FocusedWindow(
+                self.windows.iter().find(|w| w.is_focused).cloned(),
+            )),
+            Request::Action(action) => self.action(action),
+            // the stream itself is started by `serve`, once this reply is sent
+            Request::EventStream => Ok(Response::Handled),
+            request => Err(format!("fake niri does not handle {request:?}")),
+        }
+    }
+}
+
+/// The fake niri, `NIRI_SOCKET` points to it until it is dropped.
+pub struct FakeNiri {
+    state: Arc<Mutex<State>>,
+    dir: PathBuf,
+    socket_path: PathBuf,
+    stop: Arc<AtomicBool>,
+    // tests using the environment are serialized, `NIRI_SOCKET` is ours while we hold it.
+    _env: MutexGuard<'static, ()>,
+}
+
+impl FakeNiri {
+    /// Starts a niri with a single output `eDP-1`, holding a single empty workspace.
+    pub fn start() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let env_guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let dir = env::temp_dir().join(format!(
+            "swaytreesave-niri-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("create fake niri directory");
+        let socket_path = dir.join("niri.sock");
+
+        let mut state = State {
+            outputs: vec![output("eDP-1", 1)],
+            workspaces: vec![],
+            windows: vec![],
+            columns: HashMap::new(),
+            widths: HashMap::new(),
+            focused_workspace: 0,
+            next_id: 0,
+            requests: vec![],
+            spawns: vec![],
+            launched: vec![],
+            subscribers: vec![],
+        };
+        state.normalize();
+        state.focus_workspace(state.workspaces[0].id);
+
+        let state = Arc::new(Mutex::new(state));
+        let stop = Arc::new(AtomicBool::new(false));
+        let listener = UnixListener::bind(&socket_path).expect("bind fake niri socket");
+        {
+            let state = Arc::clone(&state);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(stream) = stream else { continue };
+                    let state = Arc::clone(&state);
+                    thread::spawn(move || serve(state, stream));
+                }
+            });
+        }
+
+        // SAFETY: every test touching the environment holds `ENV_LOCK`.
+        unsafe {
+            env::set_var(niri_ipc::socket::SOCKET_PATH_ENV, &socket_path);
+        }
+
+        Self {
+            state,
+            dir,
+            socket_path,
+            stop,
+            _env: env_guard,
+        }
+    }
+
+    /// A temporary directory, removed with the fake, to store tree files.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Adds a workspace holding windows of `app_ids` at the end of `eDP-1`,
+    /// before the trailing empty one.
+    pub fn add_workspace(&self, name: Option<&str>, app_ids: &[&str]) -> u64 {
+        self.add_workspace_on("eDP-1", name, app_ids)
+    }
+
+    /// Like `add_workspace`, on the given output.
+    pub fn add_workspace_on(&self, output: &str, name: Option<&str>, app_ids: &[&str]) -> u64 {
+        let mut state = lock(&self.state);
+        let focused = state.focused_workspace;
+        let id = state.add_workspace(output, name);
+        state.focused_workspace = id;
+        for app_id in app_ids {
+            state.open_window(app_id, None, None);
+        }
+        state.focus_workspace(focused);
+        id
+    }
+
+    /// Plugs a monitor, with its trailing empty workspace.
+    ///
+    /// Serials follow the plugging order, so a second monitor is the same screen whatever
+    /// the connector it is plugged into.
+    pub fn add_output(&self, name: &str) {
+        let mut state = lock(&self.state);
+        let serial = state.outputs.len() + 1;
+        state.outputs.push(output(name, serial));
+        state.normalize();
+    }
+
+    /// Opens a window at the end of the workspace with the given id.
+    pub fn add_window(&self, workspace_id: u64, app_id: &str) -> u64 {
+        let mut state = lock(&self.state);
+        let focused = state.focused_workspace;
+        state.focused_workspace = workspace_id;
+        let last = state
+            .windows
+            .iter()
+            .rfind(|w| w.workspace_id == Some(workspace_id))
+            .map(|w| w.id);
+        state.workspace_mut(workspace_id).unwrap().active_window_id = last;
+        let id = state.open_window(app_id, None, None);
+        state.focus_workspace(focused);
+        id
+    }
+
+    /// Stacks a window alone in its column at the bottom of the column on its left.
+    pub fn consume_left(&self, window_id: u64) {
+        lock(&self.state).consume_or_expel_left(window_id);
+    }
+
+    /// The columns of the workspace with the given name, left to right: the app ids of
+    /// their windows, top to bottom.
+    pub fn columns(&self, workspace: &str) -> Vec<Vec<String>> {
+        let state = lock(&self.state);
+        let Some(id) = state.resolve(&WorkspaceReferenceArg::Name(workspace.to_string())) else {
+            return vec![];
+        };
+        state
+            .workspace_columns(id)
+            .into_iter()
+            .map(|column| {
+                state
+                    .column_windows(column)
+                    .into_iter()
+                    .filter_map(|w| state.windows.iter().find(|x| x.id == w)?.app_id.clone())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The widths of the columns of the workspace with the given name, left to right, as a
+    /// proportion of the output, when one was set.
+    pub fn widths(&self, workspace: &str) -> Vec<Option<f64>> {
+        let state = lock(&self.state);
+        let Some(id) = state.resolve(&WorkspaceReferenceArg::Name(workspace.to_string())) else {
+            return vec![];
+        };
+        state
+            .workspace_columns(id)
+            .into_iter()
+            .map(|column| state.widths.get(&column).copied())
+            .collect()
+    }
+
+    /// Registers a window to open when `spawn.command` is launched.
+    pub fn on_spawn(&self, spawn: Spawn) {
+        lock(&self.state).spawns.push(spawn);
+    }
+
+    /// Stands in for the launch of the apps: no process is started, the windows expected
+    /// from `on_spawn` open instead, from made up pids.
+    pub fn launcher(&self) -> Launch {
+        let state = Arc::clone(&self.state);
+        Box::new(move |exec, cwd| Ok(lock(&state).launch(&state, &exec.to_string(), cwd)))
+    }
+
+    /// Every launched command, in order.
+    pub fn launched(&self) -> Vec<String> {
+        lock(&self.state).launched.clone()
+    }
+
+    /// Every request received, in order.
+    pub fn requests(&self) -> Vec<Request> {
+        lock(&self.state).requests.clone()
+    }
+
+    /// Every action received, in order.
+    pub fn actions(&self) -> Vec<Action> {
+        lock(&self.state)
+            .requests
+            .iter()
+            .filter_map(|r| match r {
+                Request::Action(action) => Some(action.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Workspaces, by output and index.
+    pub fn workspaces(&self) -> Vec<Workspace> {
+        let mut workspaces = lock(&self.state).workspaces.clone();
+        workspaces.sort_by(|a, b| (&a.output, a.idx).cmp(&(&b.output, b.idx)));
+        workspaces
+    }
+
+    /// The output of the workspace with the given name.
+    pub fn output_of(&self, workspace: &str) -> Option<String> {
+        let state = lock(&self.state);
+        let id = state.resolve(&WorkspaceReferenceArg::Name(workspace.to_string()))?;
+        state.workspace(id)?.output.clone()
+    }
+
+    /// App ids of the windows on the workspace with the given name, left to right.
+    pub fn app_ids(&self, workspace: &str) -> Vec<String> {
+        let state = lock(&self.state);
+        let Some(id) = state.resolve(&WorkspaceReferenceArg::Name(workspace.to_string())) else {
+            return vec![];
+        };
+        state
+            .windows
+            .iter()
+            .filter(|w| w.workspace_id == Some(id))
+            .filter_map(|w| w.app_id.clone())
+            .collect()
+    }
+}
+
+impl Drop for FakeNiri {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // wakes the accept loop up so it can see `stop`
+        let _ = UnixStream::connect(&self.socket_path);
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn output(name: &str, serial: usize) -> Output {
+    Output {
+        name: name.to_string(),
+        make: "Fake".to_string(),
+        model: "Screen".to_string(),
+        serial: Some(serial.to_string()),
+        physical_size: None,
+        modes: vec![],
+        current_mode: None,
+        vrr_supported: false,
+        vrr_enabled: false,
+        logical: Some(LogicalOutput {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+            scale: 1.0,
+            transform: Transform::Normal,
+        }),
+    }
+}
+
+fn lock(state: &Arc<Mutex<State>>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn serve(state: Arc<Mutex<State>>, stream: UnixStream) {
+    let mut writer = stream.try_clone().expect("clone fake niri stream");
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let request = serde_json::from_str::<Request>(&line);
+        let reply: Reply = match &request {
+            Ok(request) => lock(&state).handle(request.clone()),
+            Err(e) => Err(format!("error parsing request: {e}")),
+        };
+        let mut body = serde_json::to_string(&reply).expect("serialize reply");
+        body.push('\n');
+        if writer.write_all(body.as_bytes()).is_err() {
+            return;
+        }
+
+        if let Ok(Request::EventStream) = request {
+            // like niri, the stream starts with the current state
+            let mut state = lock(&state);
+            let windows = state.windows.clone();
+            state
+                .subscribers
+                .push(writer.try_clone().expect("clone fake niri stream"));
+            state.emit(&Event::WindowsChanged { windows });
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+
+use crate::models::{Node, NodeType};
+
+/// Matches the live tree against the saved one, workspace by workspace.
+///
+/// Windows are matched like a spawned window is recognized, see `Node::matches`, keeping their
+/// relative order (a longest common subsequence), so one missing window does not shift and
+/// restart all the ones after it.
+/// Matched saved windows get the `id` of the live window they keep, and the returned ids
+/// are the live windows to close: the unmatched ones, and the ones on workspaces that are
+/// not in the saved tree. Only `workspace` is considered when given.
+pub fn reconcile(saved: &mut [Node], live: &[Node], workspace: Option<&str>) -> Vec<i64> {
+    let in_scope = |name: &Option<String>| workspace.is_none() || name.as_deref() == workspace;
+
+    let mut kept = HashSet::new();
+    for saved_ws in saved.iter_mut() {
+        if !in_scope(&saved_ws.name) {
+            continue;
+        }
+        let Some(live_ws) = live.iter().find(|ws| ws.name == saved_ws.name) else {
+            continue;
+        };
+
+        let live_windows = windows(live_ws);
+        let mut saved_windows = windows_mut(saved_ws);
+        for (saved_idx, live_idx) in common_subsequence(&saved_windows, &live_windows) {
+            saved_windows[saved_idx].id = live_windows[live_idx].id;
+            kept.extend(live_windows[live_idx].id);
+        }
+    }
+
+    live.iter()
+        .filter(|ws| in_scope(&ws.name))
+        .flat_map(windows)
+        .filter_map(|window| window.id)
+        .filter(|id| !kept.contains(id))
+        .collect()
+}
+
+fn is_window(node: &Node) -> bool {
+    node.nodes.is_empty()
+        && node.layout.is_none()
+        && matches!(
+            node.node_type,
+            NodeType::Con | NodeType::FloatingCon | NodeType::Unknown
+        )
+}
+
+/// The windows under `node`, in tree order.
+pub fn windows(node: &Node) -> Vec<&Node> {
+    let mut found = vec![];
+    for child in node.nodes.iter() {
+        if is_window(child) {
+            found.push(child);
+        } else {
+            found.extend(windows(child));
+        }
+    }
+    found
+}
+
+fn windows_mut(node: &mut Node) -> Vec<&mut Node> {
+    let mut found = vec![];
+    for child in node.nodes.iter_mut() {
+        if is_window(child) {
+            found.push(child);
+        } else {
+            found.extend(windows_mut(child));
+        }
+    }
+    found
+}
+
+/// Returns `(saved index, live index)` pairs of the longest common subsequence of windows.
+fn common_subsequence(saved: &[&mut Node], live: &[&Node]) -> Vec<(usize, usize)> {
+    let same = |s: &Node, l: &Node| s.matches(l);
+
+    // lengths[i][j]: length of the LCS of saved[i..] and live[j..]
+    let mut lengths = vec![vec![0_usize; live.len() + 1]; saved.len() + 1];
+    for i in (0..saved.len()).rev() {
+        for j in (0..live.len()).rev() {
+            lengths[i][j] = if same(saved[i], live[j]) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < saved.len() && j < live.len() {
+        if same(saved[i], live[j]) {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Match, NodeLayout};
+
+    fn window(app_id: &str, id: Option<i64>) -> Node {
+        Node {
+            app_id: Some(app_id.to_string()),
+            node_type: NodeType::Con,
+            id,
+            ..Default::default()
+        }
+    }
+
+    fn workspace(name: &str, nodes: Vec<Node>) -> Node {
+        Node {
+            name: Some(name.to_string()),
+            node_type: NodeType::Workspace,
+            layout: NodeLayout::SplitH,
+            nodes,
+            ..Default::default()
+        }
+    }
+
+    fn ids(ws: &Node) -> Vec<Option<i64>> {
+        windows(ws).iter().map(|w| w.id).collect()
+    }
+
+    #[test]
+    fn keeps_matching_windows_in_order() {
+        let mut saved = vec![workspace(
+            "1",
+            vec![
+                window("foot", None),
+                window("firefox", None),
+                window("slack", None),
+            ],
+        )];
+        // firefox crashed, and an xterm was opened after slack
+        let live = vec![workspace(
+            "1",
+            vec![
+                window("foot", Some(10)),
+                window("slack", Some(11)),
+                window("xterm", Some(12)),
+            ],
+        )];
+
+        let to_close = reconcile(&mut saved, &live, None);
+
+        assert_eq!(to_close, [12]);
+        assert_eq!(ids(&saved[0]), [Some(10), None, Some(11)]);
+    }
+
+    #[test]
+    fn matches_through_nested_containers() {
+        let container = Node {
+            node_type: NodeType::Con,
+            layout: NodeLayout::SplitV,
+            nodes: vec![window("foot", None), window("foot", None)],
+            ..Default::default()
+        };
+        let mut saved = vec![workspace("1", vec![window("nvim", None), container])];
+        let live = vec![workspace(
+            "1",
+            vec![
+                window("nvim", Some(1)),
+                window("foot", Some(2)),
+                window("foot", Some(3)),
+            ],
+        )];
+
+        let to_close = reconcile(&mut saved, &live, None);
+
+        assert!(to_close.is_empty());
+        assert_eq!(ids(&saved[0]), [Some(1), Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn closes_windows_of_unsaved_workspaces_in_scope() {
+        let mut saved = vec![workspace("1", vec![window("foot", None)])];
+        let live = vec![
+            workspace("1", vec![window("foot", Some(1))]),
+            workspace("2", vec![window("discord", Some(2))]),
+        ];
+
+        assert_eq!(reconcile(&mut saved.clone(), &live, None), [2]);
+        // loading only workspace 1 leaves workspace 2 alone
+        assert!(reconcile(&mut saved, &live, Some("1")).is_empty());
+    }
+
+    #[test]
+    fn matches_xwayland_windows_and_match_specs() {
+        let xterm = |id| Node {
+            class: Some("XTerm".to_string()),
+            instance: Some("xterm".to_string()),
+            node_type: NodeType::Con,
+            id,
+            ..Default::default()
+        };
+        let pwa = |title: &str, id| Node {
+            title: Some(title.to_string()),
+            ..window("firefox", id)
+        };
+        let mail = Node {
+            matcher: Some(Match {
+                title: Some("Mail$".to_string()),
+                ..Default::default()
+            }),
+            ..window("firefox", None)
+        };
+        let mut saved = vec![workspace("1", vec![xterm(None), mail])];
+        // two PWAs share the app_id, the title tells them apart
+        let live = vec![workspace(
+            "1",
+            vec![
+                xterm(Some(1)),
+                pwa("Chat", Some(2)),
+                pwa("Inbox - Mail", Some(3)),
+            ],
+        )];
+
+        assert_eq!(reconcile(&mut saved, &live, None), [2]);
+        assert_eq!(ids(&saved[0]), [Some(1), Some(3)]);
+    }
+
+    #[test]
+    fn does_not_match_windows_on_another_workspace() {
+        let mut saved = vec![workspace("1", vec![window("foot", None)])];
+        let live = vec![workspace("2", vec![window("foot", Some(1))])];
+
+        assert_eq!(reconcile(&mut saved, &live, None), [1]);
+        assert_eq!(ids(&saved[0]), [None]);
+    }
+}
@@ -1,119 +1,228 @@
-// TODO: Resize support
-// TODO: make a diff and kill not wanted windows + spawn missing ones?
-
 use std::{
-    fs,
-    path::Path,
+    collections::HashMap,
+    io::ErrorKind,
+    os::unix::net::UnixStream,
     thread,
     time::{Duration, Instant},
 };
 
 use anyhow::{Context, Result, bail};
-use serde_yaml::to_string;
+
+#[cfg(test)]
+mod fake;
 
 use crate::{
-    config,
-    consts::MAX_WAIT_DURATION,
-    models::{Node, NodeLayout, NodeType},
-    util::extract_cmdline,
+    backend::{self, Backend, Spawner},
+    config::Config,
+    models::{Exec, Node, NodeLayout, NodeType, Output, Rect},
+    util::{extract_cmdline, extract_cwd},
 };
 
-pub fn save_tree(config_file_path: &Path, dry_run: bool) -> Result<()> {
-    // build saveable tree
-    let sway_tree = swayipc::Connection::new()?
-        .get_tree()
-        .context("on Connection::new()?.get_tree()")?;
-    let mut tree = vec![];
-    for node in sway_tree.iter() {
-        if node.node_type == swayipc::NodeType::Workspace {
-            if node.name.is_none() {
-                continue;
-            };
-            if node.name.as_ref().unwrap() == "__i3_scratch" {
-                continue;
-            }
-            tree.push(parse_children(node));
+/// Prefix of the marks put on the windows while loading, to move the next ones next to them.
+const MARK_PREFIX: &str = "_swaytreesave_";
+/// Workspace the apps are spawned on by `spawn_all`, out of the way of the rebuilt layout.
+const SPAWN_WORKSPACE: &str = "_swaytreesave_spawning";
+
+pub struct Sway {
+    connection: swayipc::Connection,
+    cfg: Config,
+    dry_run: bool,
+    no_kill: bool,
+    spawner: Spawner,
+    /// The workspace being loaded and its output: the new windows are moved there
+    /// once they show up, wherever the focus went meanwhile.
+    target: Option<(String, Option<String>)>,
+}
+
+impl Sway {
+    pub fn new(cfg: Config, dry_run: bool, no_kill: bool) -> Result<Self> {
+        let connection = swayipc::Connection::new().context("on Connection::new()")?;
+        Ok(Self {
+            connection,
+            cfg,
+            dry_run,
+            no_kill,
+            spawner: Spawner::new(dry_run),
+            target: None,
+        })
+    }
+
+    /// Runs a command, only printing it in dry run.
+    fn run(&mut self, cmd: &str) -> Result<()> {
+        println!("\t{cmd:?}");
+        if !self.dry_run {
+            self.connection
+                .run_command(cmd)
+                .context(format!("on run_command({cmd})"))?;
         }
+        Ok(())
     }
+}
 
-    // TODO: sort by workspace name
+impl Backend for Sway {
+    fn get_tree(&mut self) -> Result<Vec<Node>> {
+        let sway_tree = self
+            .connection
+            .get_tree()
+            .context("on connection.get_tree()")?;
+        let outputs = live_outputs(&mut self.connection)?;
+        let mut tree = vec![];
+        for node in sway_tree.iter() {
+            if node.node_type == swayipc::NodeType::Workspace {
+                if node.name.is_none() {
+                    continue;
+                };
+                if node.name.as_ref().unwrap() == "__i3_scratch" {
+                    continue;
+                }
+                let output = sway_tree
+                    .nodes
+                    .iter()
+                    .find(|output| output.nodes.iter().any(|ws| ws.id == node.id));
+                let mut workspace =
+                    parse_children(node, &output.map(|o| o.rect).unwrap_or(node.rect));
+                workspace.output = output
+                    .and_then(|o| o.name.as_deref())
+                    .and_then(|name| outputs.iter().find(|o| o.name == name))
+                    .cloned();
+                backend::capture_terminals(&self.cfg, &mut workspace);
+                tree.push(workspace);
+            }
+        }
 
-    if dry_run {
-        println!("tree[{config_file_path:?}]:\n{tree:?}");
-        return Ok(());
+        // TODO: sort by workspace name
+
+        Ok(tree)
     }
 
-    // saving into given config file
-    let serialized_yaml = to_string(&tree).context("on to_string()")?;
-    fs::write(config_file_path, serialized_yaml)
-        .context(format!("on fs::write({})", config_file_path.display()))?;
+    fn clear(&mut self, workspace: Option<&str>) -> Result<()> {
+        // cleaning everything, `--reconcile` keeps the windows already in place instead
+        let sway_tree = self.connection.get_tree()?;
+        for node in sway_tree.iter() {
+            if node.node_type == swayipc::NodeType::Workspace {
+                if node.name.is_none() {
+                    continue;
+                };
+                if node.name.as_ref().unwrap() == "__i3_scratch" {
+                    continue;
+                }
+                if workspace.is_some() && node.name.as_deref() != workspace {
+                    continue;
+                }
+                kill_recursive(&mut self.connection, node, self.dry_run, self.no_kill)?;
+            }
+        }
 
-    //
-    println!("tree saved into: {config_file_path:?}");
+        // TODO: remove this once kill_recursive is fixed
+        if !self.dry_run && !self.no_kill {
+            thread::sleep(Duration::from_millis(100));
+        }
 
-    Ok(())
-}
+        Ok(())
+    }
 
-pub fn load_tree(
-    config: &config::Config,
-    config_file_path: &Path,
-    dry_run: bool,
-    no_kill: bool,
-    workspace: Option<String>,
-) -> Result<()> {
-    eprintln!("Loading tree from {config_file_path:?}");
+    fn close_window(&mut self, id: i64) -> Result<()> {
+        let cmd = format!("[con_id={id}] kill");
+        println!("\t{cmd:?}");
+        if !self.dry_run && !self.no_kill {
+            self.connection
+                .run_command(cmd)
+                .context(format!("Failed to kill node with id {id}"))?;
+        }
+        Ok(())
+    }
 
-    // loading tree from file
-    let file_content = fs::read_to_string(config_file_path).context(format!(
-        "on fs::read_to_string({})",
-        config_file_path.display()
-    ))?;
-    let tree: Vec<Node> =
-        serde_yaml::from_str(&file_content).context("on serde_yaml::from_str()")?;
-
-    // cleaning everything (next time just diff windows if possible rather than starting from scratch)
-    let mut connection = swayipc::Connection::new()?;
-    let sway_tree = connection.get_tree()?;
-    for node in sway_tree.iter() {
-        if node.node_type == swayipc::NodeType::Workspace {
-            if node.name.is_none() {
-                continue;
-            };
-            if node.name.as_ref().unwrap() == "__i3_scratch" {
-                continue;
+    fn focus_workspace(&mut self, node: &Node) -> Result<()> {
+        let Some(name) = &node.name else {
+            // its windows stay where they show up, not on the workspace loaded before
+            self.target = None;
+            return Ok(());
+        };
+        let cmd = format!("workspace {name}");
+        println!("{cmd:?}");
+        if !self.dry_run {
+            self.connection
+                .run_command(cmd)
+                .context(format!("Failed to switch to workspace {name}"))?;
+        }
+        self.target = Some((name.clone(), None));
+
+        let Some(saved) = &node.output else {
+            return Ok(());
+        };
+        let outputs = live_outputs(&mut self.connection)?;
+        match saved.find_in(&outputs) {
+            Some(output) => {
+                self.target = Some((name.clone(), Some(output.name.clone())));
+                self.run(&format!("move workspace to output \"{}\"", output.name))
             }
-            if workspace.is_some() && node.name != workspace {
-                continue;
+            None => {
+                eprintln!(
+                    "Output {} not found, workspace {name} stays on the current one",
+                    saved.name
+                );
+                Ok(())
             }
-            kill_recursive(&mut connection, node, dry_run, no_kill)?;
         }
     }
 
-    // TODO: remove this once kill_recursive is fixed
-    if !dry_run && !no_kill {
-        thread::sleep(Duration::from_millis(100));
+    fn load_workspace(&mut self, node: &Node) -> Result<()> {
+        load_workspace(self, node)
     }
 
-    // spawning windows
-    for node in tree.iter() {
-        if node.node_type == NodeType::Workspace {
-            if node.name.is_none() {
-                continue;
-            };
-            if node.name.as_ref().unwrap() == "__i3_scratch" {
-                continue;
-            }
-            if workspace.is_some() && node.name != workspace {
-                continue;
-            }
-            spawn_recursive(&mut connection, node, &config.desktop_exec, dry_run)?;
+    fn config(&self) -> &Config {
+        &self.cfg
+    }
+
+    fn spawner(&mut self) -> &mut Spawner {
+        &mut self.spawner
+    }
+
+    fn window_events(&mut self) -> Result<Box<dyn backend::WindowEvents>> {
+        Ok(Box::new(WindowEvents::subscribe()?))
+    }
+
+    /// Moves the new window `id` to the workspace being loaded.
+    ///
+    /// Sway drops an empty workspace once it loses the focus, so along the first window,
+    /// the workspace is moved back to its output in case it had to be created again.
+    fn place(&mut self, id: i64) -> Result<()> {
+        let Some((workspace, output)) = &mut self.target else {
+            return Ok(());
+        };
+        let cmd = format!("[con_id={id}] move container to workspace {workspace}");
+        let output = output.take();
+        self.run(&cmd)?;
+        match output {
+            Some(output) => self.run(&format!(
+                "[con_id={id}] move workspace to output \"{output}\""
+            )),
+            None => Ok(()),
         }
     }
 
-    Ok(())
+    fn focus_spawn_workspace(&mut self) -> Result<()> {
+        self.run(&format!("workspace {SPAWN_WORKSPACE}"))
+    }
 }
 
-fn parse_children(node: &swayipc::Node) -> Node {
+/// The enabled outputs.
+fn live_outputs(connection: &mut swayipc::Connection) -> Result<Vec<Output>> {
+    let outputs = connection.get_outputs().context("on get_outputs()")?;
+    Ok(outputs
+        .into_iter()
+        .filter(|output| output.active)
+        .map(|output| Output {
+            name: output.name,
+            make: Some(output.make),
+            model: Some(output.model),
+            serial: Some(output.serial),
+        })
+        .collect())
+}
+
+/// Converts a sway node, `output` is the rect of its output, floating windows are saved relative to it.
+fn parse_children(node: &swayipc::Node, output: &swayipc::Rect) -> Node {
     let name = if node.node_type == swayipc::NodeType::Workspace {
         node.name.clone()
     } else {
@@ -122,31 +231,59 @@ fn parse_children(node: &swayipc::Node) -> Node {
     let mut parent = Node {
         name,
         node_type: NodeType::from(node.node_type),
-        app_id: node.app_id.clone(),
         nodes: vec![],
         fullscreen_mode: node.fullscreen_mode,
         percent: node.percent,
         layout: NodeLayout::from(node.layout),
-        ..Default::default()
+        ..identity(node)
     };
 
     if let Some(pid) = &node.pid {
         parent.exec = match extract_cmdline(pid) {
-            Ok(cmd) => Some(cmd),
+            Ok(args) => Some(Exec::Argv(args)),
             Err(_) => {
                 eprintln!("Failed to extract command line for PID {pid}");
                 None
             }
-        }
+        };
+        parent.cwd = extract_cwd(pid).ok();
     }
 
-    for child in node.nodes.iter() {
-        parent.nodes.push(parse_children(child));
+    if node.node_type == swayipc::NodeType::FloatingCon {
+        // the position of the container, but the size of its content: what `resize set` expects
+        parent.rect = Some(Rect {
+            x: node.rect.x - output.x,
+            y: node.rect.y - output.y,
+            width: node.window_rect.width,
+            height: node.window_rect.height,
+        });
+    }
+
+    for child in node.nodes.iter().chain(node.floating_nodes.iter()) {
+        parent.nodes.push(parse_children(child, output));
     }
 
     parent
 }
 
+/// The id of a sway node, and what tells it apart when it is a window, see `Node::matches`.
+fn identity(node: &swayipc::Node) -> Node {
+    let properties = node.window_properties.as_ref();
+    let is_window = matches!(
+        node.node_type,
+        swayipc::NodeType::Con | swayipc::NodeType::FloatingCon
+    ) && node.nodes.is_empty();
+    Node {
+        id: Some(node.id),
+        pid: node.pid,
+        app_id: node.app_id.clone(),
+        class: properties.and_then(|p| p.class.clone()),
+        instance: properties.and_then(|p| p.instance.clone()),
+        title: node.name.clone().filter(|_| is_window),
+        ..Default::default()
+    }
+}
+
 fn kill_recursive(
     connection: &mut swayipc::Connection,
     node: &swayipc::Node,
@@ -165,140 +302,416 @@ fn kill_recursive(
         }
     }
 
-    for child in node.nodes.iter() {
+    for child in node.nodes.iter().chain(node.floating_nodes.iter()) {
         kill_recursive(connection, child, dry_run, no_kill)?;
     }
 
     Ok(())
 }
 
-fn spawn_recursive(
-    connection: &mut swayipc::Connection,
-    node: &Node,
-    desktop_exec: &str,
-    dry_run: bool,
-) -> Result<()> {
-    if node.node_type == NodeType::Workspace
-        && let Some(name) = &node.name
-    {
-        let cmd = format!("workspace {name}");
-        println!("{cmd:?}");
-        if !dry_run {
-            connection
-                .run_command(cmd)
-                .context(format!("Failed to switch to workspace {name}"))?;
+/// Spawns the windows of a workspace node and rebuilds its containers around them.
+///
+/// Containers can not be created empty: the first window showing up in each child is placed in
+/// the parent right after the previous one (through a mark, sway moves a window next to a marked
+/// window), then a container is split around it when the child is one, and its own children are
+/// loaded the same way. The focus does not matter, whatever the depth of the tree.
+///
+/// The windows kept by a reconcile already sit in their containers: they are left in place, only
+/// the spawned windows are placed next to them.
+fn load_workspace(sway: &mut Sway, node: &Node) -> Result<()> {
+    let mut progress = Progress::default();
+
+    let first = anchor(sway, node, &[], &mut progress)?;
+    if let Some(first) = first {
+        mark(sway, first, &mut progress)?;
+        // a kept window may sit deep in an existing container, leave it be
+        if !progress.kept.contains(&first)
+            && let Some(layout) = layout_name(node.layout)
+        {
+            sway.run(&format!("[con_id={first}] layout {layout}"))?;
         }
     }
 
-    if matches!(
-        node.node_type,
-        NodeType::Con | NodeType::FloatingCon | NodeType::Unknown
-    ) {
-        let cmd = if let Some(desktop_file) = &node.desktop_entry {
-            Some(format!(
-                "exec {} \"{}\"",
-                desktop_exec,
-                desktop_file.replace("\"", "\\\"")
-            ))
-        } else if let Some(exec) = &node.exec {
-            Some(format!("exec \"{}\"", exec.replace("\"", "\\\"")))
-        } else {
-            node.app_id.as_ref().map(|app_id| format!("exec {app_id}"))
-        };
-
-        if let Some(cmd) = cmd {
-            println!("\t{cmd:?}");
-            if !dry_run {
-                for i in 0..node.retry.unwrap_or(1) {
-                    if i > 0 {
-                        println!("\tRetrying...");
-                    }
-                    match spawn_and_wait(connection, &cmd, &node.app_id, &node.timeout) {
-                        Ok(_) => break,
-                        Err(e) => {
-                            eprintln!("{e}");
+    let result = load_children(sway, node, &[], None, &mut progress);
+
+    for id in progress.marked {
+        sway.run(&format!("unmark {MARK_PREFIX}{id}"))?;
+    }
+    result
+}
+
+/// What a load did so far, to spawn each window once whichever level asks for it first.
+#[derive(Default)]
+struct Progress {
+    /// The windows marked to place the next ones.
+    marked: Vec<i64>,
+    /// The window of each node already spawned, by the indexes leading to it from the
+    /// workspace, `None` when it did not show up.
+    windows: HashMap<Vec<usize>, Option<i64>>,
+    /// The windows kept by a reconcile.
+    kept: Vec<i64>,
+}
+
+/// Loads the children of `node`, whose first window to show up is already placed.
+///
+/// `path` leads to `node` from the workspace, `container` is the live container of `node`,
+/// `None` for a workspace or in dry run.
+fn load_children(
+    sway: &mut Sway,
+    node: &Node,
+    path: &[usize],
+    container: Option<i64>,
+    progress: &mut Progress,
+) -> Result<()> {
+    let (tiled, floating): (Vec<_>, Vec<_>) = children(node, path)
+        .into_iter()
+        .partition(|(_, child)| child.rect.is_none());
+
+    // the first window showing up in every child, in order
+    let mut windows: Vec<Option<i64>> = Vec::with_capacity(tiled.len());
+    // what the next window is placed after: a window, or `None` after a kept container
+    let mut previous: Option<Option<i64>> = None;
+    for (child_path, child) in &tiled {
+        let window = anchor(sway, child, child_path, progress)?;
+        if let Some(window) = window {
+            let kept = progress.kept.contains(&window);
+            if !kept && !progress.marked.contains(&window) {
+                match (previous, container) {
+                    (Some(Some(previous)), _) => sway.run(&format!(
+                        "[con_id={window}] move container to mark {MARK_PREFIX}{previous}"
+                    ))?,
+                    // sway moves a window to the end of a marked container
+                    (_, Some(container)) => {
+                        if !progress.marked.contains(&container) {
+                            mark(sway, container, progress)?;
                         }
+                        sway.run(&format!(
+                            "[con_id={window}] move container to mark {MARK_PREFIX}{container}"
+                        ))?;
                     }
+                    // already at the end of the workspace
+                    (_, None) => {}
                 }
             }
+            if !progress.marked.contains(&window) {
+                mark(sway, window, progress)?;
+            }
+            previous = Some((!kept || child.nodes.is_empty()).then_some(window));
         }
+        windows.push(window);
     }
 
-    for (index, child) in node.nodes.iter().enumerate() {
-        spawn_recursive(connection, child, desktop_exec, dry_run)?;
-        if index == 0 {
-            if node.layout == NodeLayout::SplitH {
-                let cmd = "split h".to_string();
-                println!("\t{cmd:?}");
-
-                if !dry_run {
-                    connection
-                        .run_command(cmd)
-                        .context("on run_command(split h)")?;
-                }
-            } else if node.layout == NodeLayout::SplitV {
-                let cmd = "split v".to_string();
-                println!("\t{cmd:?}");
-                if !dry_run {
-                    connection
-                        .run_command(cmd)
-                        .context("on run_command(split v)")?;
-                }
+    // then the containers around them
+    let mut containers = Vec::with_capacity(tiled.len());
+    for ((child_path, child), window) in tiled.iter().zip(windows) {
+        if child.nodes.is_empty() {
+            containers.push(window);
+            continue;
+        }
+        let live = match (window, split_name(child.layout)) {
+            (Some(window), _) if progress.kept.contains(&window) => {
+                child_of(sway, container, window)?
             }
+            (Some(window), Some(split)) => {
+                sway.run(&format!("[con_id={window}] {split}"))?;
+                parent_of(sway, window)?
+            }
+            _ => None,
+        };
+        // none of its windows may have shown up yet, its floating ones are still to spawn
+        load_children(sway, child, child_path, live, progress)?;
+        containers.push(live);
+    }
+
+    let tiled: Vec<_> = tiled.into_iter().map(|(_, child)| child).collect();
+    resize_children(sway, node.layout, &tiled, &containers)?;
+
+    for (child_path, child) in floating {
+        if let (Some(rect), Some(window)) = (
+            child.rect,
+            spawn_window(sway, child, &child_path, progress)?,
+        ) {
+            float_window(sway, window, &rect)?;
         }
     }
 
     Ok(())
 }
 
-fn spawn_and_wait(
-    connection: &mut swayipc::Connection,
-    cmd: &str,
-    app_id: &Option<String>,
-    timeout: &Option<Duration>,
+/// The first window of `node` to show up, spawning its tiled windows in order until one does.
+fn anchor(
+    sway: &mut Sway,
+    node: &Node,
+    path: &[usize],
+    progress: &mut Progress,
+) -> Result<Option<i64>> {
+    let tiled: Vec<_> = children(node, path)
+        .into_iter()
+        .filter(|(_, child)| child.rect.is_none())
+        .collect();
+    if tiled.is_empty() {
+        return spawn_window(sway, node, path, progress);
+    }
+    for (child_path, child) in tiled {
+        if let Some(window) = anchor(sway, child, &child_path, progress)? {
+            return Ok(Some(window));
+        }
+    }
+    Ok(None)
+}
+
+/// The children of the node at `path`, each with its own path.
+fn children<'a>(node: &'a Node, path: &[usize]) -> Vec<(Vec<usize>, &'a Node)> {
+    node.nodes
+        .iter()
+        .enumerate()
+        .map(|(idx, child)| ([path, &[idx]].concat(), child))
+        .collect()
+}
+
+/// Spawns the window `node`, at `path`, once, unless it is kept by a reconcile, returning its id.
+fn spawn_window(
+    sway: &mut Sway,
+    node: &Node,
+    path: &[usize],
+    progress: &mut Progress,
+) -> Result<Option<i64>> {
+    if let Some(window) = progress.windows.get(path) {
+        return Ok(*window);
+    }
+    let window = if let Some(id) = node.id {
+        progress.kept.push(id);
+        Some(id)
+    } else if !matches!(
+        node.node_type,
+        NodeType::Con | NodeType::FloatingCon | NodeType::Unknown
+    ) {
+        None
+    } else {
+        sway.spawn_and_wait(node)
+            .context(format!("on spawn_and_wait for node: {node:?}"))?
+    };
+    progress.windows.insert(path.to_vec(), window);
+    Ok(window)
+}
+
+fn mark(sway: &mut Sway, window: i64, progress: &mut Progress) -> Result<()> {
+    sway.run(&format!(
+        "[con_id={window}] mark --add {MARK_PREFIX}{window}"
+    ))?;
+    progress.marked.push(window);
+    Ok(())
+}
+
+/// The command creating a container of `layout` around a window.
+fn split_name(layout: NodeLayout) -> Option<&'static str> {
+    match layout {
+        NodeLayout::SplitH => Some("split h"),
+        NodeLayout::SplitV => Some("split v"),
+        NodeLayout::Tabbed => Some("split v, layout tabbed"),
+        NodeLayout::Stacked => Some("split v, layout stacking"),
+        _ => None,
+    }
+}
+
+fn layout_name(layout: NodeLayout) -> Option<&'static str> {
+    match layout {
+        NodeLayout::SplitH => Some("splith"),
+        NodeLayout::SplitV => Some("splitv"),
+        NodeLayout::Tabbed => Some("tabbed"),
+        NodeLayout::Stacked => Some("stacking"),
+        _ => None,
+    }
+}
+
+/// The id of the container holding `window`, `None` in dry run.
+fn parent_of(sway: &mut Sway, window: i64) -> Result<Option<i64>> {
+    if sway.dry_run {
+        return Ok(None);
+    }
+    let tree = sway
+        .connection
+        .get_tree()
+        .context("on connection.get_tree()")?;
+    Ok(tree
+        .iter()
+        .find(|node| node.nodes.iter().any(|child| child.id == window))
+        .map(|node| node.id))
+}
+
+/// The child of `container` (of the workspace when `None`) holding `window`, `None` in dry run
+/// or when `window` sits right in it.
+fn child_of(sway: &mut Sway, container: Option<i64>, window: i64) -> Result<Option<i64>> {
+    if sway.dry_run {
+        return Ok(None);
+    }
+    let tree = sway
+        .connection
+        .get_tree()
+        .context("on connection.get_tree()")?;
+    let Some(parent) = tree.iter().find(|node| match container {
+        Some(container) => node.id == container,
+        None => {
+            node.node_type == swayipc::NodeType::Workspace
+                && node.iter().any(|child| child.id == window)
+        }
+    }) else {
+        return Ok(None);
+    };
+    Ok(parent
+        .nodes
+        .iter()
+        .find(|child| child.id != window && child.iter().any(|node| node.id == window))
+        .map(|child| child.id))
+}
+
+/// Gives the tiled `children` of a `layout` container their saved share of it.
+///
+/// Sway takes what a child gets with `resize set` from both its neighbours, undoing the
+/// share of the ones already sized: each child grows or shrinks its edge towards the next
+/// one instead, which only moves that edge. The last child gets what remains.
+fn resize_children(
+    sway: &mut Sway,
+    layout: NodeLayout,
+    children: &[&Node],
+    containers: &[Option<i64>],
 ) -> Result<()> {
-    let before = if let Some(app_id) = &app_id {
-        count_app_ids(connection, app_id).context(format!("on count_app_ids({app_id})"))?
+    let direction = match layout {
+        NodeLayout::SplitH => "right",
+        NodeLayout::SplitV => "down",
+        _ => return Ok(()),
+    };
+    // the children that did not show up are not siblings of the others in the layout
+    let (children, containers): (Vec<_>, Vec<_>) = children
+        .iter()
+        .zip(containers)
+        .filter_map(|(child, container)| Some((child.percent, (*container)?)))
+        .unzip();
+    if children.len() < 2 {
+        return Ok(());
+    }
+
+    let mut current = if sway.dry_run {
+        shares(&vec![None; containers.len()])
     } else {
-        0
+        let tree = sway
+            .connection
+            .get_tree()
+            .context("on connection.get_tree()")?;
+        let live: Vec<_> = containers
+            .iter()
+            .map(|&id| tree.find_as_ref(|node| node.id == id)?.percent)
+            .collect();
+        shares(&live)
     };
-    connection
-        .run_command(cmd)
-        .context(format!("on run_command:{cmd:?}"))?;
-    if let Some(app_id) = &app_id {
-        let now: Instant = Instant::now();
-        while let Ok(after) = count_app_ids(connection, app_id) {
-            if after > before {
-                break;
-            }
-            if now.elapsed() > timeout.unwrap_or(MAX_WAIT_DURATION) {
-                bail!("Timed out waiting for app with id {} to spawn", app_id);
-            }
-            eprintln!("sleep 100ms");
-            thread::sleep(Duration::from_millis(100));
+    for i in 0..children.len() - 1 {
+        let Some(percent) = children[i] else {
+            continue;
+        };
+        let delta = ((percent - current[i]) * 100.0).round();
+        if delta == 0.0 {
+            continue;
         }
+        let change = if delta > 0.0 { "grow" } else { "shrink" };
+        sway.run(&format!(
+            "[con_id={}] resize {change} {direction} {} ppt",
+            containers[i],
+            delta.abs()
+        ))?;
+        current[i] += delta / 100.0;
+        current[i + 1] -= delta / 100.0;
     }
+
     Ok(())
 }
 
-fn count_app_ids(connection: &mut swayipc::Connection, app_id: &str) -> swayipc::Fallible<usize> {
-    let mut count = 0;
-    for child in connection.get_tree()?.nodes.iter() {
-        count += count_app_ids_recurse(app_id, child);
-    }
-    Ok(count)
+/// The share of their parent of children whose `percents` may be unknown: those share evenly
+/// what the known ones leave.
+fn shares(percents: &[Option<f64>]) -> Vec<f64> {
+    let known: f64 = percents.iter().flatten().sum();
+    let unknown = percents.iter().filter(|percent| percent.is_none()).count();
+    let even = (1.0 - known).max(0.0) / unknown.max(1) as f64;
+    percents
+        .iter()
+        .map(|percent| percent.unwrap_or(even))
+        .collect()
 }
 
-fn count_app_ids_recurse(app_id: &str, node: &swayipc::Node) -> usize {
-    let mut count = if node.app_id == Some(app_id.to_string()) {
-        1
+/// Floats `window` and moves it back to `rect`, relative to its output.
+fn float_window(sway: &mut Sway, window: i64, rect: &Rect) -> Result<()> {
+    let (x, y) = if sway.dry_run {
+        (0, 0)
     } else {
-        0
+        output_origin(&mut sway.connection, window).context("on output_origin()")?
     };
-    for child in node.nodes.iter() {
-        count += count_app_ids_recurse(app_id, child);
+    sway.run(&format!(
+        "[con_id={window}] floating enable, resize set width {} px height {} px, move absolute position {} {}",
+        rect.width,
+        rect.height,
+        x + rect.x,
+        y + rect.y
+    ))
+}
+
+/// The position of the output holding `window`, whichever is focused.
+fn output_origin(connection: &mut swayipc::Connection, window: i64) -> Result<(i32, i32)> {
+    let tree = connection.get_tree().context("on get_tree()")?;
+    tree.nodes
+        .iter()
+        .find(|output| output.iter().any(|node| node.id == window))
+        .map(|output| (output.rect.x, output.rect.y))
+        .context(format!("no output holds window {window}"))
+}
+
+/// The `window::new` events.
+struct WindowEvents {
+    events: swayipc::EventStream,
+    /// The socket of `events`, which hides it, to set its read timeout.
+    socket: UnixStream,
+}
+
+impl WindowEvents {
+    fn subscribe() -> Result<Self> {
+        let stream = UnixStream::from(swayipc::Connection::new().context("on Connection::new()")?);
+        let socket = stream.try_clone().context("on try_clone()")?;
+        let events = swayipc::Connection::from(stream)
+            .subscribe([swayipc::EventType::Window])
+            .context("on subscribe(window)")?;
+        Ok(Self { events, socket })
+    }
+}
+
+impl backend::WindowEvents for WindowEvents {
+    fn next_window(&mut self, deadline: Option<Instant>) -> Result<Option<Node>> {
+        loop {
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => Some(remaining),
+                    _ => return Ok(None),
+                },
+                None => None,
+            };
+            self.socket
+                .set_read_timeout(remaining)
+                .context("on set_read_timeout()")?;
+
+            match self.events.next() {
+                Some(Ok(swayipc::Event::Window(event)))
+                    if event.change == swayipc::WindowChange::New =>
+                {
+                    return Ok(Some(identity(&event.container)));
+                }
+                Some(Ok(_)) => {}
+                Some(Err(swayipc::Error::Io(e)))
+                    if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    return Ok(None);
+                }
+                Some(Err(e)) => return Err(e).context("on events.next()"),
+                None => bail!("sway closed the event stream"),
+            }
+        }
     }
-    count
 }
 
 impl From<swayipc::NodeType> for NodeType {
@@ -329,3 +742,1047 @@ impl From<swayipc::NodeLayout> for NodeLayout {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use super::{
+        fake::{FakeSway, Spawn},
+        *,
+    };
+    use crate::{
+        backend,
+        config::OnFailure,
+        models::{Multiplexer, Session, save_tree},
+    };
+
+    fn window(app_id: &str) -> Node {
+        Node {
+            app_id: Some(app_id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn workspace(name: &str, layout: NodeLayout, nodes: Vec<Node>) -> Node {
+        Node {
+            name: Some(name.to_string()),
+            node_type: NodeType::Workspace,
+            layout,
+            nodes,
+            ..Default::default()
+        }
+    }
+
+    fn spawn(app_id: &str) -> Spawn {
+        Spawn {
+            exec: app_id.to_string(),
+            app_id: app_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The commands sent to the fake, without their criteria nor the marks placing the windows.
+    fn sent(sway: &FakeSway) -> Vec<String> {
+        sway.commands()
+            .into_iter()
+            .filter(|cmd| !cmd.contains("mark "))
+            .map(|cmd| match cmd.split_once("] ") {
+                Some((criteria, cmd)) if criteria.starts_with('[') => cmd.to_string(),
+                _ => cmd,
+            })
+            .collect()
+    }
+
+    /// Writes `tree` in the fake directory and loads it.
+    fn load(sway: &FakeSway, tree: &[Node], no_kill: bool, workspace: Option<&str>) -> Result<()> {
+        let opts = Opts {
+            no_kill,
+            workspace,
+            ..Default::default()
+        };
+        load_with(sway, tree, opts)
+    }
+
+    /// A backend launching the apps through the fake.
+    fn backend(sway: &FakeSway, cfg: Config, dry_run: bool, no_kill: bool) -> Sway {
+        let mut backend = Sway::new(cfg, dry_run, no_kill).unwrap();
+        backend.spawner.launch = sway.launcher();
+        backend
+    }
+
+    /// How `load_with` loads a tree.
+    #[derive(Default)]
+    struct Opts<'a> {
+        cfg: Config,
+        dry_run: bool,
+        no_kill: bool,
+        workspace: Option<&'a str>,
+        reconcile: bool,
+        parallel: bool,
+    }
+
+    fn load_with(sway: &FakeSway, tree: &[Node], opts: Opts) -> Result<()> {
+        let tree_path = sway.dir().join("tree.yaml");
+        save_tree(&tree_path, &tree.to_vec())?;
+        let mut backend = backend(sway, opts.cfg, opts.dry_run, opts.no_kill);
+        backend::load(
+            &mut backend,
+            &tree_path,
+            opts.workspace,
+            opts.reconcile,
+            opts.parallel,
+        )
+    }
+
+    /// Loads `tree`, keeping the windows already matching it.
+    fn load_reconciled(sway: &FakeSway, tree: &[Node]) -> Result<()> {
+        let opts = Opts {
+            reconcile: true,
+            ..Default::default()
+        };
+        load_with(sway, tree, opts)
+    }
+
+    /// The tree as saved from the fake.
+    fn saved() -> Vec<Node> {
+        Sway::new(Config::default(), false, false)
+            .unwrap()
+            .get_tree()
+            .unwrap()
+    }
+
+    #[test]
+    fn saves_workspaces_and_windows() {
+        let sway = FakeSway::start();
+        sway.add_window("1", "foot");
+        sway.add_window("1", "firefox");
+        sway.add_window("2", "discord");
+
+        let tree = saved();
+
+        assert_eq!(tree.len(), 2);
+        let app_ids = |name: &str| -> Vec<_> {
+            let ws = tree
+                .iter()
+                .find(|ws| ws.name.as_deref() == Some(name))
+                .unwrap();
+            assert_eq!(ws.node_type, NodeType::Workspace);
+            ws.nodes.iter().map(|n| n.app_id.clone().unwrap()).collect()
+        };
+        assert_eq!(app_ids("1"), ["foot", "firefox"]);
+        assert_eq!(app_ids("2"), ["discord"]);
+    }
+
+    #[test]
+    fn load_kills_then_spawns_in_order() {
+        let sway = FakeSway::start();
+        let old = sway.add_window("1", "old");
+        sway.on_exec(spawn("foot"));
+        sway.on_exec(spawn("firefox"));
+
+        let tree = [workspace(
+            "1",
+            NodeLayout::SplitH,
+            vec![window("foot"), window("firefox")],
+        )];
+        load(&sway, &tree, false, None).unwrap();
+
+        assert_eq!(
+            sent(&sway),
+            [
+                "kill",
+                "workspace 1",
+                "move container to workspace 1",
+                "layout splith",
+                "move container to workspace 1",
+            ]
+        );
+        assert_eq!(sway.commands()[0], format!("[con_id={old}] kill"));
+        assert_eq!(sway.workspace("1").unwrap().app_ids(), ["foot", "firefox"]);
+    }
+
+    #[test]
+    fn load_with_no_kill_keeps_windows() {
+        let sway = FakeSway::start();
+        sway.add_window("1", "old");
+        sway.on_exec(spawn("foot"));
+
+        let tree = [workspace("1", NodeLayout::SplitH, vec![window("foot")])];
+        load(&sway, &tree, true, None).unwrap();
+
+        assert_eq!(
+            sent(&sway),
+            [
+                "workspace 1",
+                "move container to workspace 1",
+                "layout splith"
+            ]
+        );
+        assert_eq!(sway.workspace("1").unwrap().app_ids(), ["old", "foot"]);
+    }
+
+    #[test]
+    fn load_single_workspace_leaves_others_alone() {
+        let sway = FakeSway::start();
+        sway.add_window("1", "keep");
+        let old = sway.add_window("2", "old");
+        sway.on_exec(spawn("foot"));
+        sway.on_exec(spawn("discord"));
+
+        let tree = [
+            workspace("1", NodeLayout::SplitH, vec![window("foot")]),
+            workspace("2", NodeLayout::SplitH, vec![window("discord")]),
+        ];
+        load(&sway, &tree, false, Some("2")).unwrap();
+
+        assert_eq!(
+            sent(&sway),
+            [
+                "kill",
+                "workspace 2",
+                "move container to workspace 2",
+                "layout splith"
+            ]
+        );
+        assert_eq!(sway.commands()[0], format!("[con_id={old}] kill"));
+        assert_eq!(sway.workspace("1").unwrap().app_ids(), ["keep"]);
+        assert_eq!(sway.workspace("2").unwrap().app_ids(), ["discord"]);
+    }
+
+    #[test]
+    fn load_gives_up_after_timeout_and_goes_on() {
+        let sway = FakeSway::start();
+        sway.on_exec(spawn("foot"));
+        sway.on_exec(Spawn {
+            delay: Duration::from_millis(50),
+            ..spawn("slow")
+        });
+
+        let tree = [workspace(
+            "1",
+            NodeLayout::SplitV,
+            vec![
+                Node {
+                    retry: Some(2),
+                    timeout: Some(Duration::from_millis(200)),
+                    ..window("never")
+                },
+                window("slow"),
+                window("foot"),
+            ],
+        )];
+        load(&sway, &tree, false, None).unwrap();
+
+        assert_eq!(
+            sent(&sway),
+            [
+                "workspace 1",
+                "move container to workspace 1",
+                "layout splitv",
+                "move container to workspace 1",
+            ]
+        );
+        assert_eq!(sway.launched(), ["never", "never", "slow", "foot"]);
+        assert_eq!(sway.workspace("1").unwrap().app_ids(), ["slow", "foot"]);
+    }
+
+    #[test]
+    fn load_goes_on_with_a_container_whose_first_window_does_not_show_up() {
+        let sway = FakeSway::start();
+        for app_id in ["btop", "foot"] {
+            sway.on_exec(spawn(app_id));
+        }
+
+        let column = Node {
+            node_type: NodeType::Con,
+            layout: NodeLayout::SplitV,
+            nodes: vec![
+                Node {
+                    timeout: Some(Duration::from_millis(100)),
+                    ..window("htop")
+                },
+                window("btop"),
+            ],
+            ..Default::default()
+        };
+        let tree = [workspace(
+            "1",
+            NodeLayout::SplitH,
+            vec![column, window("foot")],
+        )];
+        load(&sway, &tree, false, None).unwrap();
+
+        assert_eq!(sway.launched(), ["htop", "btop", "foot"]);
+        let saved = saved();
+        assert_eq!(shape(&saved[0]), "SplitH[SplitV[btop] foot]");
+    }
+
+    #[test]
+    fn load_waits_for_window_events_instead_of_polling() {
+        let sway = FakeSway::start();
+        sway.on_exec(Spawn {
+            delay: Duration::from_millis(300),
+            ..spawn("slow")
+        });
+
+        let tree = [workspace("1", NodeLayout::SplitH, vec![window("slow")])];
+        load(&sway, &tree, false, None).unwrap();
+
+        assert_eq!(sway.workspace("1").unwrap().app_ids(), ["slow"]);
+        // the tree is only read once, to know what to kill
+        assert_eq!(sway.get_trees(), 1);
+    }
+
+    #[test]
+    fn load_in_parallel_places_windows_like_a_sequential_load() {
+        let sway = FakeSway::start();
+        for app_id in ["foot", "htop", "btop", "discord"] {
+            sway.on_exec(Spawn {
+                delay: Duration::from_millis(400),
+                ..spawn(app_id)
+            });
+        }
+
+        let column = Node {
+            node_type: NodeType::Con,
+            layout: NodeLayout::SplitV,
+            nodes: vec![window("htop"), window("btop")],
+            ..Default::default()
+        };
+        let tree = [
+            workspace("1", NodeLayout::SplitH, vec![window("foot"), column]),
+            workspace("2", NodeLayout::SplitH, vec![window("discord")]),
+        ];
+        let opts = Opts {
+            parallel: true,
+            ..Default::default()
+        };
+        load_with(&sway, &tree, opts).unwrap();
+
+        // all launched on the spawning workspace before the first one is placed
+        assert_eq!(
+            sent(&sway)[..2],
+            [
+                format!("workspace {SPAWN_WORKSPACE}"),
+                "workspace 1".to_string()
+            ]
+        );
+        assert_eq!(sway.launched(), ["foot", "htop", "btop", "discord"]);
+        let saved = saved();
+        let names: Vec<_> = saved.iter().filter_map(|ws| ws.name.as_deref()).collect();
+        assert_eq!(names.len(), 2, "the spawning workspace is gone: {names:?}");
+        let saved = |name: &str| saved.iter().find(|ws| ws.name.as_deref() == Some(name));
+        assert_eq!(shape(saved("1").unwrap()), "SplitH[foot SplitV[htop btop]]");
+        assert_eq!(shape(saved("2").unwrap()), shape(&tree[1]));
+    }
+
+    #[test]
+    fn load_places_windows_when_the_focus_moves_away() {
+        let sway = FakeSway::start();
+        sway.on_exec(Spawn {
+            delay: Duration::from_millis(100),
+            steal_focus: Some("9".to_string()),
+            ..spawn("foot")
+        });
+        sway.on_exec(spawn("htop"));
+        sway.on_exec(spawn("btop"));
+
+        let column = Node {
+            node_type: NodeType::Con,
+            layout: NodeLayout::SplitV,
+            nodes: vec![window("htop"), window("btop")],
+            ..Default::default()
+        };
+        let tree = [workspace(
+            "1",
+            NodeLayout::SplitH,
+            vec![window("foot"), column],
+        )];
+        load(&sway, &tree, false, None).unwrap();
+
+        let saved = saved();
+        let saved = |name: &str| saved.iter().find(|ws| ws.name.as_deref() == Some(name));
+        assert_eq!(shape(saved("1").unwrap()), "SplitH[foot SplitV[htop btop]]");
+        assert!(saved("9").is_none_or(|ws| ws.nodes.is_empty()));
+    }
+
+    #[test]
+    fn load_floats_windows_on_their_output_when_the_focus_moves_away() {
+        let sway = FakeSway::start();
+        let hdmi = Rect {
+            x: 1920,
+            y: 0,
+            width: 2560,
+            height: 1440,
+        };
+        sway.add_output("HDMI-A-1", hdmi, "2");
+        sway.on_exec(spawn("foot"));
+        sway.on_exec(Spawn {
+            steal_focus: Some("1".to_string()),
+            ..spawn("mpv")
+        });
+
+        let pip = Node {
+            node_type: NodeType::FloatingCon,
+            rect: Some(Rect {
+                x: 100,
+                y: 100,
+                width: 640,
+                height: 360,
+            }),
+            ..window("mpv")
+        };
+        let tree = [workspace(
+            "2",
+            NodeLayout::SplitH,
+            vec![window("foot"), pip],
+        )];
+        load(&sway, &tree, false, None).unwrap();
+
+        let ws = sway.workspace("2").unwrap();
+        assert!(ws.iter().any(|n| n.app_id.as_deref() == Some("mpv")));
+        // relative to HDMI-A-1, where the window is, not to the focused output
+        assert!(sent(&sway).contains(&"move absolute position 2020 100".to_string()));
+    }
+
+    #[test]
+    fn load_waits_for_xwayland_windows_by_class() {
+        let sway = FakeSway::start();
+        sway.on_exec(Spawn {
+            delay: Duration::from_millis(100),
+            class: Some("XTerm".to_string()),
+            title: Some("xterm".to_string()),
+            ..spawn("xterm")
+        });
+
+        let xterm = Node {
+            exec: Some(Exec::Shell("xterm".to_string())),
+            class: Some("XTerm".to_string()),
+            ..Default::default()
+        };
+        let tree = [workspace("1", NodeLayout::SplitH, vec![xterm])];
+        load(&sway, &tree, false, None).unwrap();
+
+        assert!(sent(&sway).contains(&"move container to workspace 1".to_string()));
+        let saved = saved();
+        let window = &saved[0].nodes[0];
+        assert_eq!(window.app_id, None);
+        assert_eq!(window.class.as_deref(), Some("XTerm"));
+        assert_eq!(window.instance.as_deref(), Some("xterm"));
+        assert_eq!(window.title.as_deref(), Some("xterm"));
+    }
+
+    #[test]
+    fn load_falls_back_to_app_id_when_the_app_hands_over_its_window() {
+        let sway = FakeSway::start();
+        sway.on_exec(Spawn {
+            handover: true,
+            ..spawn("firefox")
+        });
+
+        let tree = [workspace("1", NodeLayout::SplitH, vec![window("firefox")])];
+        load(&sway, &tree, false, None).unwrap();
+
+        assert!(sent(&sway).contains(&"move container to workspace 1".to_string()));
+        assert_eq!(sway.workspace("1").unwrap().app_ids(), ["firefox"]);
+    }
+
+    #[test]
+    fn load_aborts_on_failure_when_configured() {
+        let sway = FakeSway::start();
+        sway.on_exec(spawn("foot"));
+
+        let tree = [workspace(
+            "1",
+            NodeLayout::SplitV,
+            vec![
+                Node {
+                    timeout: Some(Duration::from_millis(200)),
+                    ..window("never")
+                },
+                window("foot"),
+            ],
+        )];
+        let cfg = Config {
+            on_failure: OnFailure::Abort,
+            ..Default::default()
+        };
+        let opts = Opts {
+            cfg,
+            ..Default::default()
+        };
+
+        assert!(load_with(&sway, &tree, opts).is_err());
+        assert_eq!(sent(&sway), ["workspace 1"]);
+        assert_eq!(sway.launched(), ["never"]);
+    }
+
+    #[test]
+    fn load_attaches_terminals_to_their_session() {
+        let sway = FakeSway::start();
+        sway.on_exec(Spawn {
+            exec: "foot -e tmux new-session -A -s work".to_string(),
+            ..spawn("foot")
+        });
+        sway.on_exec(spawn("foot"));
+
+        let terminal = |session: Option<Session>| Node {
+            session,
+            ..window("foot")
+        };
+        let tree = [workspace(
+            "1",
+            NodeLayout::SplitH,
+            vec![
+                terminal(Some(Session {
+                    multiplexer: Multiplexer::Tmux,
+                    name: "work".to_string(),
+                    server: vec![],
+                })),
+                terminal(None),
+            ],
+        )];
+        let cfg = Config {
+            terminals: HashMap::from([(
+                "foot".to_string(),
+                vec!["foot".to_string(), "-e".to_string()],
+            )]),
+            ..Default::default()
+        };
+        let opts = Opts {
+            cfg,
+            ..Default::default()
+        };
+        load_with(&sway, &tree, opts).unwrap();
+
+        assert_eq!(
+            sway.launched(),
+            ["foot -e tmux new-session -A -s work", "foot"]
+        );
+    }
+
+    #[test]
+    fn dry_run_sends_no_command() {
+        let sway = FakeSway::start();
+        sway.add_window("1", "old");
+
+        let tree = [workspace("1", NodeLayout::SplitH, vec![window("foot")])];
+        let opts = Opts {
+            dry_run: true,
+            ..Default::default()
+        };
+        load_with(&sway, &tree, opts).unwrap();
+
+        assert!(sway.commands().is_empty());
+    }
+
+    #[test]
+    fn load_rebuilds_tabbed_container_in_split() {
+        let sway = FakeSway::start();
+        for app_id in ["foot", "firefox", "docs"] {
+            sway.on_exec(spawn(app_id));
+        }
+
+        let tabs = Node {
+            node_type: NodeType::Con,
+            layout: NodeLayout::Tabbed,
+            nodes: vec![window("firefox"), window("docs")],
+            ..Default::default()
+        };
+        let tree = [workspace(
+            "1",
+            NodeLayout::SplitH,
+            vec![window("foot"), tabs],
+        )];
+        load(&sway, &tree, false, None).unwrap();
+
+        assert_eq!(
+            sent(&sway),
+            [
+                "workspace 1",
+                "move container to workspace 1",
+                "layout splith",
+                "move container to workspace 1",
+                "split v",
+                "layout tabbed",
+                "move container to workspace 1",
+            ]
+        );
+        let ws = sway.workspace("1").unwrap();
+        let tabbed = ws.iter().find(|n| n.layout == "tabbed").unwrap();
+        assert_eq!(tabbed.app_ids(), ["firefox", "docs"]);
+        assert_eq!(ws.app_ids(), ["foot", "firefox", "docs"]);
+    }
+
+    #[test]
+    fn load_rebuilds_split_in_stacked_container() {
+        let sway = FakeSway::start();
+        for app_id in ["foot", "htop", "btop"] {
+            sway.on_exec(spawn(app_id));
+        }
+
+        let split = Node {
+            node_type: NodeType::Con,
+            layout: NodeLayout::SplitV,
+            nodes: vec![window("htop"), window("btop")],
+            ..Default::default()
+        };
+        let stack = Node {
+            node_type: NodeType::Con,
+            layout: NodeLayout::Stacked,
+            nodes: vec![window("foot"), split],
+            ..Default::default()
+        };
+        let tree = [workspace("1", NodeLayout::SplitH, vec![stack])];
+        load(&sway, &tree, false, None).unwrap();
+
+        let ws = sway.workspace("1").unwrap();
+        let stacked = ws.iter().find(|n| n.layout == "stacked").unwrap();
+        assert_eq!(stacked.nodes.len(), 2);
+        assert_eq!(stacked.nodes[0].app_id.as_deref(), Some("foot"));
+        assert_eq!(stacked.nodes[1].layout, "splitv");
+        assert_eq!(stacked.nodes[1].app_ids(), ["htop", "btop"]);
+    }
+
+    /// A one line description of the layout under `node`, e.g. `splith[foot firefox]`.
+    fn shape(node: &Node) -> String {
+        if node.nodes.is_empty() {
+            return node.app_id.clone().unwrap_or_default();
+        }
+        let children: Vec<_> = node.nodes.iter().map(shape).collect();
+        format!("{:?}[{}]", node.layout, children.join(" "))
+    }
+
+    #[test]
+    fn load_rebuilds_three_levels_of_containers() {
+        let sway = FakeSway::start();
+        for app_id in ["nvim", "foot", "firefox", "docs", "htop", "slack"] {
+            sway.on_exec(spawn(app_id));
+        }
+
+        let container = |layout, nodes| Node {
+            node_type: NodeType::Con,
+            layout,
+            nodes,
+            ..Default::default()
+        };
+        let tabs = container(NodeLayout::Tabbed, vec![window("firefox"), window("docs")]);
+        let column = container(
+            NodeLayout::SplitV,
+            vec![
+                window("foot"),
+                tabs,
+                container(NodeLayout::SplitH, vec![window("htop"), window("slack")]),
+            ],
+        );
+        let tree = [workspace(
+            "1",
+            NodeLayout::SplitH,
+            vec![window("nvim"), column],
+        )];
+        load(&sway, &tree, false, None).unwrap();
+
+        let saved = saved();
+        assert_eq!(
+            shape(&saved[0]),
+            "SplitH[nvim SplitV[foot Tabbed[firefox docs] SplitH[htop slack]]]"
+        );
+        assert_eq!(shape(&saved[0]), shape(&tree[0]));
+        let ws = sway.workspace("1").unwrap();
+        assert!(ws.iter().all(|node| node.marks.is_empty()));
+    }
+
+    #[test]
+    fn load_leaves_the_windows_of_unnamed_workspaces_where_they_show_up() {
+        let sway = FakeSway::start();
+        sway.on_exec(spawn("foot"));
+        sway.on_exec(spawn("firefox"));
+
+        let unnamed = Node {
+            name: None,
+            ..workspace("", NodeLayout::SplitH, vec![window("firefox")])
+        };
+        let tree = [
+            workspace("1", NodeLayout::SplitH, vec![window("foot")]),
+            unnamed,
+        ];
+        load(&sway, &tree, false, None).unwrap();
+
+        assert_eq!(
+            sent(&sway),
+            [
+                "workspace 1",
+                "move container to workspace 1",
+                "layout splith",
+                "layout splith",
+            ]
+        );
+    }
+
+    #[test]
+    fn load_restores_split_proportions() {
+        let sway = FakeSway::start();
+        sway.on_exec(spawn("foot"));
+        sway.on_exec(spawn("firefox"));
+
+        let sized = |app_id: &str, percent: f64| Node {
+            percent: Some(percent),
+            ..window(app_id)
+        };
+        let tree = [workspace(
+            "1",
+            NodeLayout::SplitH,
+            vec![sized("foot", 0.7), sized("firefox", 0.3)],
+        )];
+        load(&sway, &tree, false, None).unwrap();
+
+        let ws = sway.workspace("1").unwrap();
+        let foot = ws
+            .iter()
+            .find(|n| n.app_id.as_deref() == Some("foot"))
+            .unwrap();
+        assert_eq!(
+            sent(&sway),
+            [
+                "workspace 1",
+                "move container to workspace 1",
+                "layout splith",
+                "move container to workspace 1",
+                "resize grow right 20 ppt",
+            ]
+        );
+        assert_eq!(foot.percent, Some(0.7));
+    }
+
+    #[test]
+    fn load_restores_split_proportions_of_more_than_two_children() {
+        let sway = FakeSway::start();
+        sway.on_exec(spawn("foot"));
+        sway.on_exec(spawn("firefox"));
+        sway.on_exec(spawn("slack"));
+
+        let sized = |app_id: &str, percent: f64| Node {
+            percent: Some(percent),
+            ..window(app_id)
+        };
+        let tree = [workspace(
+            "1",
+            NodeLayout::SplitH,
+            vec![
+                sized("foot", 0.5),
+                sized("firefox", 0.25),
+                sized("slack", 0.25),
+            ],
+        )];
+        load(&sway, &tree, false, None).unwrap();
+
+        // sizing firefox leaves foot alone, within the rounding to whole ppt
+        let ws = sway.workspace("1").unwrap();
+        let percents: Vec<_> = ws.iter().filter_map(|n| n.percent).collect();
+        assert_eq!(percents.len(), 3);
+        for (percent, saved) in percents.into_iter().zip([0.5, 0.25, 0.25]) {
+            assert!((percent - saved).abs() < 0.01, "{percent} is not {saved}");
+        }
+    }
+
+    #[test]
+    fn load_restores_nested_split_proportions() {
+        let sway = FakeSway::start();
+        sway.on_exec(spawn("foot"));
+        sway.on_exec(spawn("firefox"));
+        sway.on_exec(spawn("slack"));
+
+        let sized = |app_id: &str, percent: f64| Node {
+            percent: Some(percent),
+            ..window(app_id)
+        };
+        let column = Node {
+            node_type: NodeType::Con,
+            layout: NodeLayout::SplitV,
+            percent: Some(0.4),
+            nodes: vec![sized("firefox", 0.25), sized("slack", 0.75)],
+            ..Default::default()
+        };
+        let tree = [workspace(
+            "1",
+            NodeLayout::SplitH,
+            vec![sized("foot", 0.6), column],
+        )];
+        load(&sway, &tree, false, None).unwrap();
+
+        let ws = sway.workspace("1").unwrap();
+        let percent = |app_id: &str| {
+            ws.iter()
+                .find(|n| n.app_id.as_deref() == Some(app_id))
+                .unwrap()
+                .percent
+        };
+        assert_eq!(ws.app_ids(), ["foot", "firefox", "slack"]);
+        assert_eq!(percent("foot"), Some(0.6));
+        assert_eq!(percent("firefox"), Some(0.25));
+        // the last child of a container takes what remains
+        assert_eq!(percent("slack"), Some(0.75));
+        assert!(sent(&sway).contains(&"resize shrink down 25 ppt".to_string()));
+    }
+
+    #[test]
+    fn saves_floating_windows_relative_to_their_output() {
+        let sway = FakeSway::start();
+        let hdmi = Rect {
+            x: 1920,
+            y: 0,
+            width: 2560,
+            height: 1440,
+        };
+        sway.add_output("HDMI-A-1", hdmi, "2");
+        sway.add_window("2", "foot");
+        let pip = Rect {
+            x: 2020,
+            y: 100,
+            width: 640,
+            height: 360,
+        };
+        sway.add_floating_window("2", "mpv", pip);
+
+        let tree = saved();
+
+        let ws = tree
+            .iter()
+            .find(|ws| ws.name.as_deref() == Some("2"))
+            .unwrap();
+        assert_eq!(ws.nodes.len(), 2);
+        assert_eq!(ws.nodes[0].rect, None);
+        assert_eq!(ws.nodes[1].node_type, NodeType::FloatingCon);
+        assert_eq!(
+            ws.nodes[1].rect,
+            Some(Rect {
+                x: 100,
+                y: 100,
+                width: 640,
+                height: 360,
+            })
+        );
+    }
+
+    #[test]
+    fn load_puts_floating_windows_back_in_place() {
+        let sway = FakeSway::start();
+        let hdmi = Rect {
+            x: 1920,
+            y: 0,
+            width: 2560,
+            height: 1440,
+        };
+        sway.add_output("HDMI-A-1", hdmi, "2");
+        sway.on_exec(spawn("foot"));
+        sway.on_exec(spawn("mpv"));
+
+        let pip = Node {
+            node_type: NodeType::FloatingCon,
+            rect: Some(Rect {
+                x: 100,
+                y: 100,
+                width: 640,
+                height: 360,
+            }),
+            ..window("mpv")
+        };
+        let tree = [workspace(
+            "2",
+            NodeLayout::SplitH,
+            vec![window("foot"), pip],
+        )];
+        load(&sway, &tree, false, None).unwrap();
+
+        let ws = sway.workspace("2").unwrap();
+        let mpv = ws
+            .iter()
+            .find(|n| n.app_id.as_deref() == Some("mpv"))
+            .unwrap();
+        assert_eq!(
+            sent(&sway),
+            [
+                "workspace 2",
+                "move container to workspace 2",
+                "layout splith",
+                "move container to workspace 2",
+                "floating enable",
+                "resize set width 640 px height 360 px",
+                "move absolute position 2020 100",
+            ]
+        );
+        assert_eq!(mpv.node_type, "floating_con");
+        assert_eq!(
+            mpv.rect,
+            Rect {
+                x: 2020,
+                y: 100,
+                width: 640,
+                height: 360,
+            }
+        );
+        assert_eq!(ws.app_ids(), ["foot"]);
+    }
+
+    #[test]
+    fn saves_the_output_of_each_workspace() {
+        let sway = FakeSway::start();
+        sway.add_output("HDMI-A-1", Rect::default(), "2");
+        sway.add_window("1", "foot");
+        sway.add_window("2", "discord");
+
+        let tree = saved();
+
+        let output = |name: &str| {
+            tree.iter()
+                .find(|ws| ws.name.as_deref() == Some(name))
+                .and_then(|ws| ws.output.clone())
+                .unwrap()
+        };
+        assert_eq!(output("1").name, "eDP-1");
+        let hdmi = output("2");
+        assert_eq!(hdmi.name, "HDMI-A-1");
+        assert_eq!(hdmi.make.as_deref(), Some("Fake"));
+        assert!(hdmi.serial.is_some());
+        assert_ne!(hdmi.serial, output("1").serial);
+    }
+
+    #[test]
+    fn load_moves_workspaces_back_to_their_monitor() {
+        let sway = FakeSway::start();
+        sway.add_output("HDMI-A-1", Rect::default(), "2");
+        sway.add_window("2", "discord");
+        let saved = saved();
+        let hdmi = saved
+            .iter()
+            .find(|ws| ws.name.as_deref() == Some("2"))
+            .and_then(|ws| ws.output.clone());
+        // the same monitor, plugged into another port
+        sway.rename_output("HDMI-A-1", "DP-2");
+        sway.on_exec(spawn("slack"));
+
+        let tree = [Node {
+            output: hdmi,
+            ..workspace("3", NodeLayout::SplitH, vec![window("slack")])
+        }];
+        load(&sway, &tree, true, None).unwrap();
+
+        assert_eq!(
+            sent(&sway)[..3],
+            [
+                "workspace 3",
+                "move workspace to output \"DP-2\"",
+                "move container to workspace 3",
+            ]
+        );
+        assert_eq!(sway.output_of("3").as_deref(), Some("DP-2"));
+        assert_eq!(sway.workspace("3").unwrap().app_ids(), ["slack"]);
+    }
+
+    #[test]
+    fn load_keeps_workspaces_of_missing_monitors_on_the_current_output() {
+        let sway = FakeSway::start();
+        sway.on_exec(spawn("slack"));
+
+        let tree = [Node {
+            output: Some(Output {
+                name: "HDMI-A-1".to_string(),
+                serial: Some("unplugged".to_string()),
+                ..Default::default()
+            }),
+            ..workspace("3", NodeLayout::SplitH, vec![window("slack")])
+        }];
+        load(&sway, &tree, true, None).unwrap();
+
+        assert_eq!(
+            sent(&sway),
+            [
+                "workspace 3",
+                "move container to workspace 3",
+                "layout splith"
+            ]
+        );
+        assert_eq!(sway.output_of("3").as_deref(), Some("eDP-1"));
+    }
+
+    #[test]
+    fn reconcile_keeps_windows_in_place() {
+        let sway = FakeSway::start();
+        let foot = sway.add_window("1", "foot");
+        let slack = sway.add_window("1", "slack");
+        sway.add_window("1", "xterm");
+        let discord = sway.add_window("2", "discord");
+        sway.on_exec(spawn("firefox"));
+
+        let tree = [
+            workspace(
+                "1",
+                NodeLayout::SplitH,
+                vec![window("foot"), window("firefox"), window("slack")],
+            ),
+            workspace("2", NodeLayout::SplitH, vec![window("discord")]),
+        ];
+        load_reconciled(&sway, &tree).unwrap();
+
+        assert_eq!(
+            sent(&sway),
+            [
+                "kill",
+                "workspace 1",
+                "move container to workspace 1",
+                "workspace 2"
+            ]
+        );
+        let ws = sway.workspace("1").unwrap();
+        assert_eq!(ws.app_ids(), ["foot", "firefox", "slack"]);
+        assert!(ws.find(foot).is_some() && ws.find(slack).is_some());
+        assert!(sway.workspace("2").unwrap().find(discord).is_some());
+    }
+
+    #[test]
+    fn reconcile_leaves_kept_containers_alone() {
+        let sway = FakeSway::start();
+        for app_id in ["htop", "btop", "foot", "nvim", "firefox"] {
+            sway.on_exec(spawn(app_id));
+        }
+
+        let column = |nodes| Node {
+            node_type: NodeType::Con,
+            layout: NodeLayout::SplitV,
+            nodes,
+            ..Default::default()
+        };
+        let tree = [workspace(
+            "1",
+            NodeLayout::SplitH,
+            vec![column(vec![window("htop"), window("btop")]), window("foot")],
+        )];
+        load(&sway, &tree, false, None).unwrap();
+        let shape_of = || {
+            let saved = saved();
+            shape(&saved[0])
+        };
+        assert_eq!(shape_of(), "SplitH[SplitV[htop btop] foot]");
+
+        for _ in 0..2 {
+            load_reconciled(&sway, &tree).unwrap();
+            assert_eq!(shape_of(), "SplitH[SplitV[htop btop] foot]");
+        }
+
+        let tree = [workspace(
+            "1",
+            NodeLayout::SplitH,
+            vec![
+                column(vec![window("htop"), window("btop"), window("nvim")]),
+                window("foot"),
+                window("firefox"),
+            ],
+        )];
+        load_reconciled(&sway, &tree).unwrap();
+        assert_eq!(shape_of(), "SplitH[SplitV[htop btop nvim] foot firefox]");
+        assert_eq!(sway.launched()[3..], ["firefox", "nvim"]);
+        let ws = sway.workspace("1").unwrap();
+        assert!(ws.iter().all(|node| node.marks.is_empty()));
+    }
+}
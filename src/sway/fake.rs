@@ -0,0 +1,1040 @@
+//! An in-process stand-in for sway, speaking the i3/sway IPC protocol on a temporary
+//! unix socket, so `Sway` can be tested on a machine without a compositor.
+//!
+//! It serves a scripted tree, records every `run_command` and simulates windows
+//! appearing after an `exec`, following a small subset of sway semantics.
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use serde_json::{Value, json};
+
+use crate::{backend::Launch, models::Rect, util::ENV_LOCK};
+
+const MAGIC: &[u8; 6] = b"i3-ipc";
+
+const RUN_COMMAND: u32 = 0;
+const GET_WORKSPACES: u32 = 1;
+const SUBSCRIBE: u32 = 2;
+const GET_OUTPUTS: u32 = 3;
+const GET_TREE: u32 = 4;
+const GET_VERSION: u32 = 7;
+/// Event types have their highest bit set.
+const WINDOW_EVENT: u32 = 0x8000_0003;
+
+/// A window to create when `exec` is launched.
+#[derive(Clone, Debug, Default)]
+pub struct Spawn {
+    /// The launched command, as a shell reads it: the script, or the quoted arguments.
+    pub exec: String,
+    /// When given, only a launch in this directory opens the window.
+    pub cwd: Option<PathBuf>,
+    pub app_id: String,
+    /// X11 class of a XWayland window, which then has no app_id.
+    pub class: Option<String>,
+    pub title: Option<String>,
+    /// How long the app takes to show its window.
+    pub delay: Duration,
+    /// How many launches are ignored before the window shows up, to simulate a flaky app.
+    pub skip: u32,
+    /// Workspace focused right before the window shows up, as when the user moves on while
+    /// the app starts: like sway, the window opens there.
+    pub steal_focus: Option<String>,
+    /// The window is opened by another process than the launched one, like an app handing
+    /// over to its running instance.
+    pub handover: bool,
+}
+
+/// The pid of the first launch, past the pids of the real processes.
+const FIRST_PID: i32 = 1 << 23;
+
+#[derive(Clone, Debug)]
+pub struct FakeNode {
+    pub id: i64,
+    pub node_type: &'static str,
+    pub name: Option<String>,
+    pub app_id: Option<String>,
+    /// X11 class of a XWayland window, its instance is the class in lowercase.
+    pub class: Option<String>,
+    pub pid: Option<i32>,
+    pub layout: String,
+    pub percent: Option<f64>,
+    pub marks: Vec<String>,
+    /// Absolute, like sway's.
+    pub rect: Rect,
+    /// Tiled and floating (`floating_con`) children, split apart in the json.
+    pub nodes: Vec<FakeNode>,
+}
+
+impl FakeNode {
+    fn new(id: i64, node_type: &'static str) -> Self {
+        Self {
+            id,
+            node_type,
+            name: None,
+            app_id: None,
+            class: None,
+            pid: None,
+            layout: "none".to_string(),
+            percent: None,
+            marks: vec![],
+            rect: Rect::default(),
+            nodes: vec![],
+        }
+    }
+
+    pub fn find(&self, id: i64) -> Option<&FakeNode> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.nodes.iter().find_map(|child| child.find(id))
+    }
+
+    fn find_mut(&mut self, id: i64) -> Option<&mut FakeNode> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.nodes.iter_mut().find_map(|child| child.find_mut(id))
+    }
+
+    fn parent_of(&self, id: i64) -> Option<&FakeNode> {
+        if self.nodes.iter().any(|child| child.id == id) {
+            return Some(self);
+        }
+        self.nodes.iter().find_map(|child| child.parent_of(id))
+    }
+
+    /// Depth first iterator over this node and all its descendants.
+    pub fn iter(&self) -> impl Iterator<Item = &FakeNode> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.nodes.iter().rev());
+            Some(node)
+        })
+    }
+
+    /// Calls `f` on this node and all its descendants.
+    fn for_each_mut(&mut self, f: &mut impl FnMut(&mut FakeNode)) {
+        f(self);
+        for child in self.nodes.iter_mut() {
+            child.for_each_mut(f);
+        }
+    }
+
+    /// The app ids of the windows under this node, in tree order.
+    pub fn app_ids(&self) -> Vec<String> {
+        self.iter()
+            .filter(|node| node.node_type == "con" && node.nodes.is_empty())
+            .filter_map(|node| node.app_id.clone())
+            .collect()
+    }
+
+    fn to_json(&self, focused: i64) -> Value {
+        let rect = json!(self.rect);
+        let window_rect =
+            json!({ "x": 0, "y": 0, "width": self.rect.width, "height": self.rect.height });
+        let (floating, tiled): (Vec<_>, Vec<_>) = self
+            .nodes
+            .iter()
+            .partition(|n| n.node_type == "floating_con");
+        json!({
+            "id": self.id,
+            "name": self.name,
+            "type": self.node_type,
+            "border": "none",
+            "current_border_width": 0,
+            "layout": self.layout,
+            "percent": self.percent,
+            "marks": self.marks,
+            "rect": rect,
+            "window_rect": window_rect,
+            "deco_rect": rect,
+            "geometry": rect,
+            "urgent": false,
+            "focused": self.id == focused,
+            "focus": [],
+            "nodes": tiled.iter().map(|n| n.to_json(focused)).collect::<Vec<_>>(),
+            "floating_nodes": floating.iter().map(|n| n.to_json(focused)).collect::<Vec<_>>(),
+            "sticky": false,
+            "app_id": self.app_id,
+            "window_properties": self.class.as_ref().map(|class| json!({
+                "class": class,
+                "instance": class.to_lowercase(),
+                "title": self.name,
+            })),
+            "pid": self.pid,
+        })
+    }
+}
+
+struct State {
+    root: FakeNode,
+    /// Focused node ids, most recent last.
+    focus: Vec<i64>,
+    next_id: i64,
+    commands: Vec<String>,
+    /// Every command launched through `FakeSway::launcher`, in order.
+    launched: Vec<String>,
+    spawns: Vec<Spawn>,
+    /// Connections subscribed to window events.
+    subscribers: Vec<UnixStream>,
+    get_trees: usize,
+}
+
+impl State {
+    fn next_id(&mut self) -> i64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn focused(&self) -> i64 {
+        *self.focus.last().expect("something is always focused")
+    }
+
+    fn focus(&mut self, id: i64) {
+        self.focus.retain(|&f| f != id);
+        self.focus.push(id);
+    }
+
+    fn workspace_of(&self, id: i64) -> Option<i64> {
+        let mut current = id;
+        loop {
+            let node = self.root.find(current)?;
+            if node.node_type == "workspace" {
+                return Some(node.id);
+            }
+            current = self.root.parent_of(current)?.id;
+        }
+    }
+
+    /// Like sway, drops the workspace `id` once it is empty, unless it is focused
+    /// or the last one of its output.
+    fn reap_workspace(&mut self, id: i64) {
+        let Some(output) = self.root.parent_of(id).map(|p| p.id) else {
+            return;
+        };
+        if self.workspace_of(self.focused()) == Some(id)
+            || !self.root.find(id).unwrap().nodes.is_empty()
+        {
+            return;
+        }
+        let output = self.root.find_mut(output).unwrap();
+        if output.nodes.len() > 1 {
+            output.nodes.retain(|node| node.id != id);
+            self.focus.retain(|&f| f != id);
+        }
+    }
+
+    /// The workspace `name`, created on the focused output if needed.
+    fn workspace_or_create(&mut self, name: &str) -> i64 {
+        if let Some(id) = self.workspace_by_name(name) {
+            return id;
+        }
+        let output = self
+            .root
+            .parent_of(self.workspace_of(self.focused()).unwrap())
+            .unwrap()
+            .id;
+        self.add_workspace(output, name)
+    }
+
+    /// Focuses the workspace `name`, creating it if needed.
+    fn switch_workspace(&mut self, name: &str) {
+        let workspace = self.workspace_or_create(name);
+        let last_focused = self
+            .focus
+            .iter()
+            .rev()
+            .copied()
+            .find(|&f| self.workspace_of(f) == Some(workspace))
+            .unwrap_or(workspace);
+        let left = self.workspace_of(self.focused()).unwrap();
+        self.focus(last_focused);
+        self.reap_workspace(left);
+    }
+
+    fn workspace_by_name(&self, name: &str) -> Option<i64> {
+        self.root
+            .iter()
+            .find(|n| n.node_type == "workspace" && n.name.as_deref() == Some(name))
+            .map(|n| n.id)
+    }
+
+    fn add_workspace(&mut self, output: i64, name: &str) -> i64 {
+        let id = self.next_id();
+        let mut workspace = FakeNode::new(id, "workspace");
+        workspace.name = Some(name.to_string());
+        workspace.layout = "splith".to_string();
+        self.root
+            .find_mut(output)
+            .expect("output exists")
+            .nodes
+            .push(workspace);
+        id
+    }
+
+    /// Opens a window of the process `pid` next to the focused node, like sway does,
+    /// and focuses it.
+    fn open_window(&mut self, spawn: &Spawn, pid: Option<i32>) -> i64 {
+        let id = self.next_id();
+        let mut window = FakeNode::new(id, "con");
+        window.app_id = Some(spawn.app_id.clone()).filter(|_| spawn.class.is_none());
+        window.class = spawn.class.clone();
+        // the title of a window is its name
+        window.name = spawn.title.clone();
+        window.pid = pid;
+
+        let anchor = self.focused();
+        let anchor_type = self.root.find(anchor).map(|n| n.node_type);
+        if anchor_type == Some("workspace") {
+            self.root.find_mut(anchor).unwrap().nodes.push(window);
+        } else {
+            let parent = self.root.parent_of(anchor).unwrap().id;
+            let parent = self.root.find_mut(parent).unwrap();
+            let idx = parent.nodes.iter().position(|n| n.id == anchor).unwrap();
+            parent.nodes.insert(idx + 1, window);
+        }
+        self.focus(id);
+        let container = self.root.find(id).unwrap().to_json(id);
+        let event = message(
+            WINDOW_EVENT,
+            &json!({ "change": "new", "container": container }),
+        );
+        self.subscribers
+            .retain_mut(|stream| stream.write_all(&event).is_ok());
+        id
+    }
+
+    fn remove(&mut self, id: i64) {
+        let Some(parent) = self.root.parent_of(id).map(|p| p.id) else {
+            return;
+        };
+        let workspace = self.workspace_of(id);
+        let had_focus = self
+            .focus
+            .last()
+            .is_some_and(|&f| self.root.find(id).unwrap().find(f).is_some());
+
+        let parent_node = self.root.find_mut(parent).unwrap();
+        parent_node.nodes.retain(|n| n.id != id);
+        let reap = parent_node.node_type == "con" && parent_node.nodes.is_empty();
+        self.focus.retain(|&f| self.root.find(f).is_some());
+        if reap {
+            self.remove(parent);
+        }
+
+        if (had_focus || self.focus.is_empty())
+            && let Some(workspace) = workspace
+        {
+            let next = self
+                .focus
+                .iter()
+                .rev()
+                .copied()
+                .find(|&f| self.workspace_of(f) == Some(workspace))
+                .unwrap_or(workspace);
+            self.focus(next);
+        }
+    }
+
+    fn run(&mut self, criteria: Option<&str>, command: &str) -> Value {
+        let target = match criteria.and_then(|c| c.strip_prefix("con_id=")) {
+            Some(id) => match id
+                .parse::<i64>()
+                .ok()
+                .filter(|&id| self.root.find(id).is_some())
+            {
+                Some(id) => id,
+                None => return json!({ "success": false, "error": "No matching node." }),
+            },
+            None => self.focused(),
+        };
+
+        let (verb, args) = command.split_once(' ').unwrap_or((command, ""));
+        match verb {
+            "workspace" => self.switch_workspace(args),
+            "kill" => self.remove(target),
+            "focus" if args.is_empty() => self.focus(target),
+            "split" => {
+                let layout = match args {
+                    "h" | "horizontal" => "splith",
+                    "v" | "vertical" => "splitv",
+                    _ => return json!({ "success": false, "error": "Invalid split command." }),
+                };
+                self.split(target, layout);
+            }
+            "floating" if args == "enable" => self.float(target),
+            "mark" => {
+                let (add, mark) = match args.strip_prefix("--add ") {
+                    Some(mark) => (true, mark),
+                    None => (false, args),
+                };
+                let mark = mark.to_string();
+                self.root
+                    .for_each_mut(&mut |node| node.marks.retain(|m| *m != mark));
+                let node = self.root.find_mut(target).unwrap();
+                if !add {
+                    node.marks.clear();
+                }
+                node.marks.push(mark);
+            }
+            "unmark" => {
+                self.root
+                    .for_each_mut(&mut |node| node.marks.retain(|m| !args.is_empty() && m != args));
+            }
+            "move" if args.starts_with("workspace to output ") => {
+                let name = unquote(&args["workspace to output ".len()..]);
+                let Some(output) = self
+                    .root
+                    .nodes
+                    .iter()
+                    .find(|o| o.name.as_deref() == Some(&name))
+                    .map(|o| o.id)
+                else {
+                    return json!({ "success": false, "error": "No output matched." });
+                };
+                let workspace = self.workspace_of(target).unwrap();
+                let from = self.root.parent_of(workspace).unwrap().id;
+                let from = self.root.find_mut(from).unwrap();
+                let idx = from.nodes.iter().position(|n| n.id == workspace).unwrap();
+                let workspace = from.nodes.remove(idx);
+                self.root.find_mut(output).unwrap().nodes.push(workspace);
+            }
+            "move" if args.starts_with("container to workspace ") => {
+                let name = unquote(&args["container to workspace ".len()..]);
+                // like sway, a missing workspace is created on the focused output
+                let workspace = match name.as_str() {
+                    "current" => self.workspace_of(self.focused()).unwrap(),
+                    name => self.workspace_or_create(name),
+                };
+                let from = self.workspace_of(target).unwrap();
+                if from != workspace {
+                    let parent = self.root.parent_of(target).unwrap().id;
+                    let parent_node = self.root.find_mut(parent).unwrap();
+                    let idx = parent_node
+                        .nodes
+                        .iter()
+                        .position(|n| n.id == target)
+                        .unwrap();
+                    let window = parent_node.nodes.remove(idx);
+                    let reap = parent_node.node_type == "con" && parent_node.nodes.is_empty();
+                    self.root.find_mut(workspace).unwrap().nodes.push(window);
+                    if reap {
+                        self.remove(parent);
+                    }
+                    self.reap_workspace(from);
+                }
+            }
+            "move" if args.starts_with("container to mark ") => {
+                let mark = &args["container to mark ".len()..];
+                if !self.move_to_mark(target, mark) {
+                    return json!({ "success": false, "error": "Cannot move to mark." });
+                }
+            }
+            "layout" => {
+                let layout = match args {
+                    "splith" | "splitv" | "tabbed" => args,
+                    "stacking" => "stacked",
+                    _ => return json!({ "success": false, "error": "Invalid layout command." }),
+                };
+                // like sway, the layout of a window is the one of its parent
+                let container = match self.root.find(target).unwrap().node_type {
+                    "workspace" => target,
+                    _ => self.root.parent_of(target).unwrap().id,
+                };
+                self.root.find_mut(container).unwrap().layout = layout.to_string();
+            }
+            "move" => {
+                let position = args
+                    .strip_prefix("absolute position ")
+                    .and_then(|a| a.split_once(' '))
+                    .and_then(|(x, y)| Some((x.parse::<i32>().ok()?, y.parse::<i32>().ok()?)));
+                let Some((x, y)) = position else {
+                    return json!({ "success": false, "error": "Invalid move command." });
+                };
+                let node = self.root.find_mut(target).unwrap();
+                node.rect.x = x;
+                node.rect.y = y;
+            }
+            "resize" if args.ends_with(" px") => {
+                let size = args
+                    .strip_prefix("set width ")
+                    .and_then(|a| a.strip_suffix(" px"))
+                    .and_then(|a| a.split_once(" px height "))
+                    .and_then(|(w, h)| Some((w.parse::<i32>().ok()?, h.parse::<i32>().ok()?)));
+                let Some((width, height)) = size else {
+                    return json!({ "success": false, "error": "Invalid resize command." });
+                };
+                let node = self.root.find_mut(target).unwrap();
+                node.rect.width = width;
+                node.rect.height = height;
+            }
+            "resize" => {
+                let parsed = args
+                    .split_once(' ')
+                    .filter(|(change, _)| matches!(*change, "grow" | "shrink"))
+                    .and_then(|(change, a)| Some((change, a.strip_suffix(" ppt")?)))
+                    .and_then(|(change, a)| Some((change, a.split_once(' ')?)))
+                    .and_then(|(change, (direction, ppt))| {
+                        Some((change, direction, ppt.parse::<f64>().ok()?))
+                    });
+                let (change, layout, ppt) = match parsed {
+                    Some((change, "right", ppt)) => (change, "splith", ppt),
+                    Some((change, "down", ppt)) => (change, "splitv", ppt),
+                    _ => return json!({ "success": false, "error": "Invalid resize command." }),
+                };
+                let delta = if change == "grow" { ppt } else { -ppt } / 100.0;
+                if !self.resize(target, layout, delta) {
+                    return json!({ "success": false, "error": "Cannot resize any further." });
+                }
+            }
+            _ => {}
+        }
+        json!({ "success": true })
+    }
+
+    /// Wraps `target` into a new container, or changes the layout of a workspace.
+    fn split(&mut self, target: i64, layout: &str) {
+        if self.root.find(target).unwrap().node_type == "workspace" {
+            self.root.find_mut(target).unwrap().layout = layout.to_string();
+            return;
+        }
+        let parent_id = self.root.parent_of(target).unwrap().id;
+        let parent = self.root.find_mut(parent_id).unwrap();
+        // like i3, sway does not split a lone window in a split container but changes its layout
+        let siblings = parent
+            .nodes
+            .iter()
+            .filter(|n| n.node_type != "floating_con")
+            .count();
+        if siblings == 1 && (parent.layout == "splith" || parent.layout == "splitv") {
+            parent.layout = layout.to_string();
+            return;
+        }
+        let id = self.next_id();
+        let parent = self.root.find_mut(parent_id).unwrap();
+        let idx = parent.nodes.iter().position(|n| n.id == target).unwrap();
+        let child = parent.nodes.remove(idx);
+        let mut container = FakeNode::new(id, "con");
+        container.layout = layout.to_string();
+        container.nodes.push(child);
+        parent.nodes.insert(idx, container);
+    }
+
+    /// Moves `target` next to the window marked `mark`, or in the container marked `mark`.
+    fn move_to_mark(&mut self, target: i64, mark: &str) -> bool {
+        let Some(dest) = self
+            .root
+            .iter()
+            .find(|n| n.marks.iter().any(|m| m == mark))
+            .map(|n| n.id)
+        else {
+            return false;
+        };
+        let node = self.root.find(target).unwrap();
+        if node.find(dest).is_some() {
+            return false;
+        }
+        let parent = self.root.parent_of(target).unwrap().id;
+        let parent_node = self.root.find_mut(parent).unwrap();
+        let idx = parent_node
+            .nodes
+            .iter()
+            .position(|n| n.id == target)
+            .unwrap();
+        let window = parent_node.nodes.remove(idx);
+        let reap = parent_node.node_type == "con" && parent_node.nodes.is_empty();
+
+        let dest_node = self.root.find(dest).unwrap();
+        if dest_node.node_type == "con" && dest_node.nodes.is_empty() {
+            let dest_parent = self.root.parent_of(dest).unwrap().id;
+            let dest_parent = self.root.find_mut(dest_parent).unwrap();
+            let idx = dest_parent.nodes.iter().position(|n| n.id == dest).unwrap();
+            dest_parent.nodes.insert(idx + 1, window);
+        } else {
+            self.root.find_mut(dest).unwrap().nodes.push(window);
+        }
+        if reap {
+            self.remove(parent);
+        }
+        true
+    }
+
+    /// Moves `target` out of its container, to the floating windows of its workspace.
+    fn float(&mut self, target: i64) {
+        let workspace = self.workspace_of(target).unwrap();
+        let parent = self.root.parent_of(target).unwrap().id;
+        let parent_node = self.root.find_mut(parent).unwrap();
+        let idx = parent_node
+            .nodes
+            .iter()
+            .position(|n| n.id == target)
+            .unwrap();
+        let mut window = parent_node.nodes.remove(idx);
+        let reap = parent_node.node_type == "con" && parent_node.nodes.is_empty();
+        window.node_type = "floating_con";
+        self.root.find_mut(workspace).unwrap().nodes.push(window);
+        if reap {
+            self.remove(parent);
+        }
+    }
+
+    /// Grows the share of `target`, or of its first ancestor laid out by a `layout` parent, by
+    /// `delta`, taking it from its next sibling like sway moving the edge between them.
+    fn resize(&mut self, target: i64, layout: &str, delta: f64) -> bool {
+        let mut current = target;
+        while let Some(parent) = self.root.parent_of(current) {
+            if parent.layout != layout {
+                current = parent.id;
+                continue;
+            }
+            let parent = parent.id;
+            let parent = self.root.find_mut(parent).unwrap();
+            let mut tiled: Vec<_> = parent
+                .nodes
+                .iter_mut()
+                .filter(|n| n.node_type != "floating_con")
+                .collect();
+            let idx = tiled.iter().position(|n| n.id == current).unwrap();
+            if idx + 1 == tiled.len() {
+                return false;
+            }
+            let percents: Vec<_> = tiled.iter().map(|n| n.percent).collect();
+            let mut shares = super::shares(&percents);
+            shares[idx] += delta;
+            shares[idx + 1] -= delta;
+            for (node, share) in tiled.iter_mut().zip(shares) {
+                node.percent = Some(share);
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Records the launch of `cmd`, opening the window of its app if one is expected,
+    /// and returns the made up pid of the launched process.
+    fn launch(&mut self, state: &Arc<Mutex<State>>, cmd: &str, cwd: Option<&Path>) -> i32 {
+        self.launched.push(cmd.to_string());
+        let pid = FIRST_PID + self.launched.len() as i32;
+        let Some(spawn) = self
+            .spawns
+            .iter_mut()
+            .find(|s| s.exec == cmd && (s.cwd.is_none() || s.cwd.as_deref() == cwd))
+        else {
+            return pid;
+        };
+        if spawn.skip > 0 {
+            spawn.skip -= 1;
+            return pid;
+        }
+        let spawn = spawn.clone();
+        if spawn.delay.is_zero() {
+            self.deliver(&spawn, pid);
+            return pid;
+        }
+        let state = Arc::clone(state);
+        thread::spawn(move || {
+            thread::sleep(spawn.delay);
+            lock(&state).deliver(&spawn, pid);
+        });
+        pid
+    }
+
+    fn deliver(&mut self, spawn: &Spawn, pid: i32) {
+        if let Some(name) = &spawn.steal_focus {
+            self.switch_workspace(name);
+        }
+        let pid = if spawn.handover { pid + 1_000_000 } else { pid };
+        self.open_window(spawn, Some(pid));
+    }
+
+    fn workspaces_json(&self) -> Value {
+        let focused_workspace = self.workspace_of(self.focused());
+        let mut workspaces = vec![];
+        for output in self.root.nodes.iter() {
+            for workspace in output.nodes.iter() {
+                workspaces.push(json!({
+                    "id": workspace.id,
+                    "num": workspace.name.as_deref().and_then(|n| n.parse::<i32>().ok()).unwrap_or(-1),
+                    "name": workspace.name,
+                    "visible": Some(workspace.id) == focused_workspace,
+                    "focused": Some(workspace.id) == focused_workspace,
+                    "urgent": false,
+                    "rect": { "x": 0, "y": 0, "width": 0, "height": 0 },
+                    "output": output.name,
+                }));
+            }
+        }
+        Value::Array(workspaces)
+    }
+
+    fn outputs_json(&self) -> Value {
+        Value::Array(
+            self.root
+                .nodes
+                .iter()
+                .map(|output| {
+                    json!({
+                        "id": output.id,
+                        "name": output.name,
+                        "make": "Fake",
+                        "model": "Screen",
+                        "serial": output.id.to_string(),
+                        "active": true,
+                        "dpms": true,
+                        "primary": false,
+                        "rect": output.rect,
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+/// The fake sway, `SWAYSOCK` points to it until it is dropped.
+pub struct FakeSway {
+    state: Arc<Mutex<State>>,
+    dir: PathBuf,
+    socket_path: PathBuf,
+    stop: Arc<AtomicBool>,
+    // tests using the environment are serialized, `SWAYSOCK` is ours while we hold it.
+    _env: MutexGuard<'static, ()>,
+}
+
+impl FakeSway {
+    /// Starts a sway with a single output `eDP-1` and an empty, focused, workspace `1`.
+    pub fn start() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let env_guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let dir = env::temp_dir().join(format!(
+            "swaytreesave-sway-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("create fake sway directory");
+        let socket_path = dir.join("sway.sock");
+
+        let mut root = FakeNode::new(1, "root");
+        root.name = Some("root".to_string());
+        let mut state = State {
+            root,
+            focus: vec![],
+            next_id: 1,
+            commands: vec![],
+            launched: vec![],
+            spawns: vec![],
+            subscribers: vec![],
+            get_trees: 0,
+        };
+        let output = state.next_id();
+        let mut output_node = FakeNode::new(output, "output");
+        output_node.name = Some("eDP-1".to_string());
+        output_node.layout = "output".to_string();
+        output_node.rect = Rect {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+        };
+        state.root.nodes.push(output_node);
+        let workspace = state.add_workspace(output, "1");
+        state.focus(workspace);
+
+        let state = Arc::new(Mutex::new(state));
+        let stop = Arc::new(AtomicBool::new(false));
+        let listener = UnixListener::bind(&socket_path).expect("bind fake sway socket");
+        {
+            let state = Arc::clone(&state);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(stream) = stream else { continue };
+                    let state = Arc::clone(&state);
+                    thread::spawn(move || serve(state, stream));
+                }
+            });
+        }
+
+        // SAFETY: every test touching the environment holds `ENV_LOCK`.
+        unsafe {
+            env::remove_var("I3SOCK");
+            env::set_var("SWAYSOCK", &socket_path);
+        }
+
+        Self {
+            state,
+            dir,
+            socket_path,
+            stop,
+            _env: env_guard,
+        }
+    }
+
+    /// A temporary directory, removed with the fake, to store tree files.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Opens a window on the given workspace, creating the workspace if needed.
+    pub fn add_window(&self, workspace: &str, app_id: &str) -> i64 {
+        let mut state = lock(&self.state);
+        let previous_focus = state.focused();
+        let workspace = match state.workspace_by_name(workspace) {
+            Some(id) => id,
+            None => {
+                let output = state.root.nodes[0].id;
+                state.add_workspace(output, workspace)
+            }
+        };
+        let last = state
+            .root
+            .find(workspace)
+            .and_then(|ws| ws.nodes.last())
+            .map(|n| n.id)
+            .unwrap_or(workspace);
+        state.focus(last);
+        let spawn = Spawn {
+            app_id: app_id.to_string(),
+            ..Default::default()
+        };
+        let id = state.open_window(&spawn, None);
+        state.focus(previous_focus);
+        id
+    }
+
+    /// Opens a floating window on the given workspace, at the absolute `rect`.
+    pub fn add_floating_window(&self, workspace: &str, app_id: &str, rect: Rect) -> i64 {
+        let id = self.add_window(workspace, app_id);
+        let mut state = lock(&self.state);
+        state.float(id);
+        state.root.find_mut(id).unwrap().rect = rect;
+        id
+    }
+
+    /// Adds an output, with an empty workspace `workspace` on it.
+    pub fn add_output(&self, name: &str, rect: Rect, workspace: &str) {
+        let mut state = lock(&self.state);
+        let id = state.next_id();
+        let mut output = FakeNode::new(id, "output");
+        output.name = Some(name.to_string());
+        output.layout = "output".to_string();
+        output.rect = rect;
+        state.root.nodes.push(output);
+        state.add_workspace(id, workspace);
+    }
+
+    /// Plugs the monitor of output `from` into another connector: same serial, new name.
+    pub fn rename_output(&self, from: &str, to: &str) {
+        let mut state = lock(&self.state);
+        let output = state
+            .root
+            .nodes
+            .iter_mut()
+            .find(|o| o.name.as_deref() == Some(from))
+            .expect("output exists");
+        output.name = Some(to.to_string());
+    }
+
+    /// The name of the output workspace `name` is on.
+    pub fn output_of(&self, name: &str) -> Option<String> {
+        let state = lock(&self.state);
+        let workspace = state.workspace_by_name(name)?;
+        state.root.parent_of(workspace)?.name.clone()
+    }
+
+    /// Registers a window to open when `spawn.exec` is launched.
+    pub fn on_exec(&self, spawn: Spawn) {
+        lock(&self.state).spawns.push(spawn);
+    }
+
+    /// Stands in for the launch of the apps: no process is started, the windows expected
+    /// from `on_exec` open instead, from made up pids.
+    pub fn launcher(&self) -> Launch {
+        let state = Arc::clone(&self.state);
+        Box::new(move |exec, cwd| Ok(lock(&state).launch(&state, &exec.to_string(), cwd)))
+    }
+
+    /// Every launched command, in order.
+    pub fn launched(&self) -> Vec<String> {
+        lock(&self.state).launched.clone()
+    }
+
+    /// How many times the tree was asked for.
+    pub fn get_trees(&self) -> usize {
+        lock(&self.state).get_trees
+    }
+
+    /// Every command received through `run_command`, in order.
+    pub fn commands(&self) -> Vec<String> {
+        lock(&self.state).commands.clone()
+    }
+
+    pub fn workspace(&self, name: &str) -> Option<FakeNode> {
+        let state = lock(&self.state);
+        let id = state.workspace_by_name(name)?;
+        state.root.find(id).cloned()
+    }
+}
+
+impl Drop for FakeSway {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // wakes the accept loop up so it can see `stop`
+        let _ = UnixStream::connect(&self.socket_path);
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn lock(state: &Arc<Mutex<State>>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn serve(state: Arc<Mutex<State>>, mut stream: UnixStream) {
+    loop {
+        let mut header = [0_u8; 14];
+        if stream.read_exact(&mut header).is_err() || &header[..6] != MAGIC {
+            return;
+        }
+        let len = u32::from_ne_bytes(header[6..10].try_into().unwrap()) as usize;
+        let message_type = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+        let mut payload = vec![0_u8; len];
+        if stream.read_exact(&mut payload).is_err() {
+            return;
+        }
+        let payload = String::from_utf8_lossy(&payload).to_string();
+
+        // held until the reply is sent, so no event goes out before a subscription reply
+        let mut guard = lock(&state);
+        let reply = match message_type {
+            RUN_COMMAND => {
+                let mut outcomes = vec![];
+                for chain in split_commands(&payload) {
+                    // criteria apply to every command of a `,` separated chain
+                    let mut criteria = None;
+                    for command in chain {
+                        guard.commands.push(command.clone());
+                        let command = match command.strip_prefix('[') {
+                            Some(rest) => {
+                                let (c, command) = rest.split_once(']').unwrap_or((rest, ""));
+                                criteria = Some(c.to_string());
+                                command.trim().to_string()
+                            }
+                            None => command,
+                        };
+                        outcomes.push(guard.run(criteria.as_deref(), &command));
+                    }
+                }
+                Value::Array(outcomes)
+            }
+            GET_WORKSPACES => guard.workspaces_json(),
+            GET_OUTPUTS => guard.outputs_json(),
+            GET_TREE => {
+                guard.get_trees += 1;
+                guard.root.to_json(guard.focused())
+            }
+            SUBSCRIBE => json!({ "success": true }),
+            GET_VERSION => json!({
+                "major": 1,
+                "minor": 10,
+                "patch": 0,
+                "human_readable": "fake",
+                "loaded_config_file_name": "",
+            }),
+            _ => json!({ "success": false }),
+        };
+
+        if stream.write_all(&message(message_type, &reply)).is_err() {
+            return;
+        }
+
+        // only window events are sent, whatever the subscription
+        if message_type == SUBSCRIBE {
+            guard
+                .subscribers
+                .push(stream.try_clone().expect("clone fake sway stream"));
+        }
+    }
+}
+
+fn message(message_type: u32, body: &Value) -> Vec<u8> {
+    let body = body.to_string();
+    let mut message = MAGIC.to_vec();
+    message.extend((body.len() as u32).to_ne_bytes());
+    message.extend(message_type.to_ne_bytes());
+    message.extend(body.as_bytes());
+    message
+}
+
+/// Splits a `run_command` payload into `;` separated chains of `,` separated commands,
+/// ignoring separators inside quotes.
+fn split_commands(payload: &str) -> Vec<Vec<String>> {
+    let mut chains = vec![vec![]];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in payload.chars() {
+        match c {
+            _ if escaped => {
+                escaped = false;
+                current.push(c);
+            }
+            '\\' => {
+                escaped = true;
+                current.push(c);
+            }
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ',' if !quoted => chains
+                .last_mut()
+                .unwrap()
+                .push(std::mem::take(&mut current)),
+            ';' if !quoted => {
+                chains
+                    .last_mut()
+                    .unwrap()
+                    .push(std::mem::take(&mut current));
+                chains.push(vec![]);
+            }
+            _ => current.push(c),
+        }
+    }
+    chains.last_mut().unwrap().push(current);
+    chains
+        .into_iter()
+        .map(|chain| {
+            chain
+                .into_iter()
+                .map(|c| c.trim().to_string())
+                .filter(|c| !c.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .filter(|chain| !chain.is_empty())
+        .collect()
+}
+
+/// Strips the quotes sway would remove from an argument.
+fn unquote(arg: &str) -> String {
+    match arg.strip_prefix('"').and_then(|a| a.strip_suffix('"')) {
+        Some(inner) => inner.replace("\\\"", "\""),
+        None => arg.to_string(),
+    }
+}
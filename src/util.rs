@@ -1,15 +1,437 @@
-use std::fs;
+#[cfg(test)]
+use std::sync::Mutex;
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+};
 
-pub fn extract_cmdline(pid: &i32) -> Result<String, std::io::Error> {
+use crate::models::{Exec, Multiplexer, Session};
+
+/// The variables a multiplexer sets for the programs in its panes, which must not reach the
+/// apps we launch or the clients we query when we run in one ourselves.
+const MULTIPLEXER_ENV: [&str; 4] = ["TMUX", "TMUX_PANE", "ZELLIJ", "ZELLIJ_SESSION_NAME"];
+
+/// Serializes the tests reading or writing process wide environment variables.
+#[cfg(test)]
+pub static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+/// The arguments of the process `pid`, as it was started.
+pub fn extract_cmdline(pid: &i32) -> Result<Vec<String>, Error> {
     let path = format!("/proc/{pid}/cmdline");
 
-    let data = fs::read(&path)?;
-    let joined = data
+    parse_cmdline(&fs::read(&path)?)
+}
+
+/// The arguments in the content of a `/proc/<pid>/cmdline` file.
+fn parse_cmdline(data: &[u8]) -> Result<Vec<String>, Error> {
+    if data.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, "empty command line"));
+    }
+    // each argument ends with a NUL, an empty one is still an argument, but a process
+    // rewriting its title (setproctitle) may leave the last one unterminated
+    let data = data.strip_suffix(&[0]).unwrap_or(data);
+    let args = data
         .split(|&b| b == 0)
-        .filter(|part| !part.is_empty())
-        .map(|part| String::from_utf8_lossy(part))
-        .collect::<Vec<_>>()
-        .join(" ");
+        .map(|part| String::from_utf8_lossy(part).into_owned())
+        .collect();
+
+    Ok(args)
+}
+
+/// The working directory of the process `pid` or, for a terminal, of the program in the
+/// foreground of its shell.
+pub fn extract_cwd(pid: &i32) -> Result<PathBuf, Error> {
+    let pid = foreground(*pid).unwrap_or(*pid);
+    fs::read_link(format!("/proc/{pid}/cwd"))
+}
+
+/// The foreground process of the terminal `pid`: the leader of the foreground process group
+/// of its shell, or the shell itself.
+fn foreground(pid: i32) -> Option<i32> {
+    let shell = shell(pid)?;
+    Some(foreground_of(shell).unwrap_or(shell))
+}
+
+/// The arguments of the program the shell of the terminal `pid` runs in its foreground,
+/// `None` when the shell waits for a command.
+pub fn extract_program(pid: &i32) -> Option<Vec<String>> {
+    let shell = shell(*pid)?;
+    let program = foreground_of(shell).filter(|&program| program != shell)?;
+    extract_cmdline(&program).ok()
+}
+
+/// The tmux or zellij session the terminal `pid` shows, when its foreground process is a
+/// client of one.
+///
+/// The session is the one named on the command line of the client or else, for tmux, the one
+/// the server tells the client is attached to and, for zellij, the one it tells its panes.
+pub fn extract_session(pid: &i32) -> Option<Session> {
+    let client = foreground(*pid)?;
+    let args = extract_cmdline(&client).ok()?;
+    let multiplexer = Multiplexer::of_client(&args)?;
+    let server = multiplexer.server_args(&args);
+    let name = match multiplexer.session_arg(&args) {
+        Some(name) => name,
+        None => match multiplexer {
+            Multiplexer::Tmux => tmux_session_of(client, &server)?,
+            Multiplexer::Zellij => zellij_session_of(client)?,
+        },
+    };
+    Some(Session {
+        multiplexer,
+        name,
+        server,
+    })
+}
+
+/// The session of the tmux client `pid`, asked to the server `server` chooses.
+fn tmux_session_of(pid: i32, server: &[String]) -> Option<String> {
+    let mut command = Command::new("tmux");
+    // the server of our own session would answer in place of the one of the client
+    for var in MULTIPLEXER_ENV {
+        command.env_remove(var);
+    }
+    command
+        .args(server)
+        .args(["list-clients", "-F", "#{client_pid} #{session_name}"]);
+    // the server socket lives in the directory the client was told to use
+    if let Some(tmpdir) = environ_var(pid, "TMUX_TMPDIR") {
+        command.env("TMUX_TMPDIR", tmpdir);
+    }
+    let output = command.stderr(Stdio::null()).output().ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| {
+            let (client, session) = line.split_once(' ')?;
+            (client.parse() == Ok(pid)).then(|| session.to_string())
+        })
+}
+
+/// The session of the zellij client `pid`, from the environment zellij gives the programs in
+/// its panes, which the client has too when started from one of them.
+fn zellij_session_of(pid: i32) -> Option<String> {
+    let mut pids = vec![pid];
+    while let Some(pid) = pids.pop() {
+        if let Some(name) = environ_var(pid, "ZELLIJ_SESSION_NAME") {
+            return Some(name);
+        }
+        pids.extend(children(pid));
+    }
+    None
+}
+
+fn environ_var(pid: i32, name: &str) -> Option<String> {
+    let data = fs::read(format!("/proc/{pid}/environ")).ok()?;
+    data.split(|&b| b == 0).find_map(|var| {
+        let var = String::from_utf8_lossy(var);
+        let (key, value) = var.split_once('=')?;
+        (key == name).then(|| value.to_string())
+    })
+}
+
+/// The shell of the terminal `pid`: its child holding a tty of its own.
+fn shell(pid: i32) -> Option<i32> {
+    // the children of an app started from a terminal share its tty, they are not shells
+    let tty = stat_field(pid, TTY_NR);
+    children(pid).into_iter().find(|&child| {
+        let child_tty = stat_field(child, TTY_NR);
+        child_tty.is_some_and(|child_tty| child_tty != 0) && child_tty != tty
+    })
+}
+
+/// The leader of the foreground process group of the tty of `pid`.
+fn foreground_of(pid: i32) -> Option<i32> {
+    stat_field(pid, TPGID)
+        .filter(|&tpgid| tpgid > 0 && Path::new(&format!("/proc/{tpgid}")).exists())
+}
+
+fn children(pid: i32) -> Vec<i32> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return vec![];
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .filter(|&child| parent_pid(child) == Some(pid))
+        .collect()
+}
+
+/// Starts `exec`, a script with `sh -c` or the arguments as they are, in its own process
+/// group so it is not tied to our terminal, and returns its pid.
+///
+/// It starts in `cwd` when given and still there, in ours otherwise. It gets our environment
+/// but the variables of the multiplexer we may run in, so a terminal attaching to a session
+/// is not seen as nested in ours. A thread waits for it, so a launcher exiting right away does
+/// not stay a zombie until we exit.
+pub fn launch(exec: &Exec, cwd: Option<&Path>) -> Result<i32, Error> {
+    let mut command = match exec {
+        Exec::Shell(script) => {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(script);
+            command
+        }
+        Exec::Argv(args) => {
+            let Some((program, args)) = args.split_first() else {
+                return Err(Error::new(ErrorKind::InvalidInput, "empty exec"));
+            };
+            let mut command = Command::new(program);
+            command.args(args);
+            command
+        }
+    };
+    if let Some(cwd) = cwd.filter(|cwd| cwd.is_dir()) {
+        command.current_dir(cwd);
+    }
+    for var in MULTIPLEXER_ENV {
+        command.env_remove(var);
+    }
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+    let pid = child.id() as i32;
+    thread::spawn(move || child.wait());
+    Ok(pid)
+}
+
+/// Whether `pid` is `ancestor` or one of its descendants, walking up its parents in `/proc`.
+pub fn descends_from(pid: i32, ancestor: i32) -> bool {
+    let mut current = pid;
+    loop {
+        if current == ancestor {
+            return true;
+        }
+        match parent_pid(current) {
+            Some(parent) if parent > 1 => current = parent,
+            _ => return false,
+        }
+    }
+}
+
+// fields of `/proc/<pid>/stat`, counted from the one after the command name
+const PPID: usize = 1;
+const TTY_NR: usize = 4;
+const TPGID: usize = 5;
+
+fn parent_pid(pid: i32) -> Option<i32> {
+    stat_field(pid, PPID)
+}
+
+fn stat_field(pid: i32, field: usize) -> Option<i32> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // the fields follow the command name, in parentheses, which may hold any character
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(field)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    #[test]
+    fn parses_command_lines_with_or_without_a_final_nul() {
+        assert_eq!(parse_cmdline(b"foot\0-e\0\0").unwrap(), ["foot", "-e", ""]);
+        // rewritten by setproctitle
+        assert_eq!(
+            parse_cmdline(b"chromium --type=renderer").unwrap(),
+            ["chromium --type=renderer"]
+        );
+        assert!(parse_cmdline(b"").is_err());
+    }
+
+    #[test]
+    fn finds_the_descendants_of_a_process() {
+        let mut child = Command::new("sh")
+            .args(["-c", "sleep 5 & wait"])
+            .spawn()
+            .unwrap();
+        let pid = child.id() as i32;
+        let me = std::process::id() as i32;
+
+        assert!(descends_from(pid, me));
+        assert!(descends_from(pid, pid));
+        assert!(!descends_from(me, pid));
+        assert!(!descends_from(pid, i32::MAX));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn launches_argv_as_is() {
+        let args = [
+            "sh",
+            "-c",
+            "sleep 5",
+            "~/My Notes/todo.md",
+            "it's $HOME",
+            "",
+        ];
+        let exec = Exec::Argv(args.iter().map(|arg| arg.to_string()).collect());
+        let pid = launch(&exec, None).unwrap();
+
+        // the command line shows up once the process is done starting
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while extract_cmdline(&pid).is_err() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(extract_cmdline(&pid).unwrap(), args);
+
+        Command::new("kill").arg(pid.to_string()).status().unwrap();
+    }
+
+    #[test]
+    fn reaps_launched_apps_once_they_exit() {
+        let pid = launch(&Exec::Shell("exit 0".to_string()), None).unwrap();
+
+        // a zombie keeps its `/proc` entry until it is waited for
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Path::new(&format!("/proc/{pid}")).exists() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!Path::new(&format!("/proc/{pid}")).exists());
+    }
+
+    #[test]
+    fn launches_apps_outside_of_our_multiplexer() {
+        let pid = {
+            let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            // SAFETY: every test touching the environment holds `ENV_LOCK`.
+            unsafe { env::set_var("TMUX", "/tmp/tmux-1000/default,1,0") };
+            let pid = launch(
+                &Exec::Argv(vec!["sleep".to_string(), "5".to_string()]),
+                None,
+            );
+            // SAFETY: as above.
+            unsafe { env::remove_var("TMUX") };
+            pid.unwrap()
+        };
+
+        assert_eq!(environ_var(pid, "PATH"), env::var("PATH").ok());
+        assert_eq!(environ_var(pid, "TMUX"), None);
+
+        Command::new("kill").arg(pid.to_string()).status().unwrap();
+    }
+
+    #[test]
+    fn launches_in_the_given_directory() {
+        let dir = env::temp_dir().canonicalize().unwrap();
+        let exec = Exec::Argv(vec!["sleep".to_string(), "5".to_string()]);
+        let pid = launch(&exec, Some(&dir)).unwrap();
+
+        // a process without a terminal is its own foreground
+        assert_eq!(extract_cwd(&pid).unwrap(), dir);
+
+        Command::new("kill").arg(pid.to_string()).status().unwrap();
+    }
+
+    #[test]
+    fn finds_the_program_running_in_a_terminal() {
+        // `script` plays the terminal, its job control shell runs `sleep` in the foreground
+        let mut terminal = Command::new("script")
+            .args(["-qc", "sh -mc 'sleep 30; true'", "/dev/null"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = terminal.id() as i32;
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let mut program = None;
+        while program.is_none() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+            program = extract_program(&pid);
+        }
+
+        assert_eq!(program.unwrap(), ["sleep", "30"]);
+        // no shell, no program
+        assert_eq!(extract_program(&(std::process::id() as i32)), None);
+
+        if let Some(shell) = shell(pid) {
+            Command::new("pkill")
+                .args(["-s", &shell.to_string()])
+                .status()
+                .unwrap();
+        }
+        terminal.kill().unwrap();
+        terminal.wait().unwrap();
+    }
+
+    /// The session extracted from a terminal whose shell runs `client`, a shell named `program`
+    /// standing in for a multiplexer client, in place of `{client}`.
+    fn session_shown(program: &str, client: &str) -> Option<Session> {
+        let dir = env::temp_dir().join(format!("swaytreesave-{program}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(program);
+        let _ = fs::remove_file(&path);
+        std::os::unix::fs::symlink("/bin/sh", &path).unwrap();
+        let script = format!(
+            "sh -mc \"{}; true\"",
+            client.replace("{client}", &path.display().to_string())
+        );
+        let mut terminal = Command::new("script")
+            .args(["-qc", &script, "/dev/null"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = terminal.id() as i32;
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let mut session = None;
+        while session.is_none() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+            session = extract_session(&pid);
+        }
+
+        if let Some(shell) = shell(pid) {
+            Command::new("pkill")
+                .args(["-s", &shell.to_string()])
+                .status()
+                .unwrap();
+        }
+        terminal.kill().unwrap();
+        terminal.wait().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        session
+    }
+
+    #[test]
+    fn finds_the_session_a_terminal_shows() {
+        assert_eq!(
+            session_shown("tmux", "{client} -c 'sleep 30; true' -L dev attach -t work"),
+            Some(Session {
+                multiplexer: Multiplexer::Tmux,
+                name: "work".to_string(),
+                server: vec!["-L".to_string(), "dev".to_string()],
+            })
+        );
+    }
 
-    Ok(joined)
+    #[test]
+    fn finds_the_zellij_session_a_terminal_shows_from_its_panes() {
+        // a plain `zellij` names no session, the programs in its panes know it
+        assert_eq!(
+            session_shown(
+                "zellij",
+                "{client} -c 'ZELLIJ_SESSION_NAME=work sleep 30; true'"
+            ),
+            Some(Session {
+                multiplexer: Multiplexer::Zellij,
+                name: "work".to_string(),
+                server: vec![],
+            })
+        );
+    }
 }